@@ -0,0 +1,162 @@
+//! Read glossaries to build stardicts from, see [crate::stardict::StarDictBuilder].
+//!
+//! In every format, a headword may be followed by its synonyms separated by `|`,
+//! such as `colour|color`.
+use crate::cli::SourceFormat;
+use crate::stardict::StarDictBuilder;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+/// An entry of a JSON Lines glossary.
+#[derive(Deserialize)]
+struct JsonEntry {
+    word: String,
+    definition: String,
+    #[serde(default)]
+    synonyms: Vec<String>,
+}
+
+/// Guess the format of a glossary from its extension.
+pub fn guess_format(path: &Path) -> Option<SourceFormat> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "tsv" | "tab" | "txt" => Some(SourceFormat::Tsv),
+        "csv" => Some(SourceFormat::Csv),
+        "jsonl" | "ndjson" => Some(SourceFormat::Jsonl),
+        _ => None,
+    }
+}
+
+/// Add an entry whose headword may be followed by synonyms separated by `|`.
+fn add_entry(builder: &mut StarDictBuilder, words: &str, definition: &str) -> Result<()> {
+    let mut words = words.split('|').map(str::trim).filter(|w| !w.is_empty());
+    let word = words.next().context("Missing headword")?;
+    builder.add(word, definition);
+    words.for_each(|synonym| builder.add_synonym(synonym, word));
+    Ok(())
+}
+
+/// Undo the escapes of a tab-separated definition: `\n`, `\t` and `\\`.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => unescaped.extend(['\\', c]),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Read the entries of a glossary into `builder`.
+///
+/// Empty lines are skipped. Tab-separated and CSV glossaries have the headwords in the
+/// first column and the definitions in the second, without a header; JSON Lines
+/// glossaries have objects with `word`, `definition` and optional `synonyms`.
+pub fn read_source(
+    reader: impl Read,
+    format: SourceFormat,
+    builder: &mut StarDictBuilder,
+) -> Result<()> {
+    match format {
+        SourceFormat::Tsv | SourceFormat::Jsonl => {
+            for (i, line) in std::io::BufReader::new(reader).lines().enumerate() {
+                let line = line.with_context(|| format!("Failed to read line {}", i + 1))?;
+                let line = line.trim_end_matches('\r');
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let added = if format == SourceFormat::Tsv {
+                    line.split_once('\t')
+                        .ok_or_else(|| anyhow!("Missing tab"))
+                        .and_then(|(words, definition)| {
+                            add_entry(builder, words, &unescape(definition))
+                        })
+                } else {
+                    serde_json::from_str::<JsonEntry>(line)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|entry| {
+                            add_entry(builder, &entry.word, &entry.definition)?;
+                            for synonym in &entry.synonyms {
+                                builder.add_synonym(synonym, entry.word.trim());
+                            }
+                            Ok(())
+                        })
+                };
+                added.with_context(|| format!("Invalid entry at line {}", i + 1))?;
+            }
+        }
+        SourceFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(reader);
+            for record in reader.records() {
+                let record = record?;
+                let line = record.position().map_or(0, |p| p.line());
+                if record.iter().all(|field| field.trim().is_empty()) {
+                    continue;
+                }
+                match (record.get(0), record.get(1)) {
+                    (Some(words), Some(definition)) => add_entry(builder, words, definition),
+                    _ => Err(anyhow!("Missing definition")),
+                }
+                .with_context(|| format!("Invalid entry at line {}", line))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_source;
+    use crate::cache::test::tempdir;
+    use crate::cli::SourceFormat;
+    use crate::stardict::{StarDict, StarDictBuilder};
+
+    #[test]
+    fn read_glossaries() {
+        let sources = [
+            (SourceFormat::Tsv, "colour|color\tn. 颜色\\n色彩\n\nrust\t铁锈\n"),
+            (SourceFormat::Csv, "colour|color,\"n. 颜色\n色彩\"\nrust,铁锈\n"),
+            (
+                SourceFormat::Jsonl,
+                "{\"word\": \"colour\", \"definition\": \"n. 颜色\\n色彩\", \"synonyms\": [\"color\"]}\n{\"word\": \"rust\", \"definition\": \"铁锈\"}\n",
+            ),
+        ];
+        for (format, source) in sources {
+            let mut builder = StarDictBuilder::new("glossary");
+            read_source(source.as_bytes(), format, &mut builder).unwrap();
+            let root = tempdir();
+            let dir = root.path().join("glossary");
+            builder.write(&dir, "glossary").unwrap();
+
+            let stardict = StarDict::new(dir).unwrap();
+            let entry = stardict.exact_lookup("color").unwrap().unwrap();
+            assert_eq!(
+                (entry.word, entry.trans.as_str()),
+                ("colour", "n. 颜色\n色彩")
+            );
+            assert_eq!(stardict.wordcount(), 2);
+        }
+
+        let mut builder = StarDictBuilder::new("glossary");
+        let err = read_source(
+            "rust\t铁锈\ncargo\n".as_bytes(),
+            SourceFormat::Tsv,
+            &mut builder,
+        );
+        assert!(format!("{:#}", err.unwrap_err()).contains("line 2"));
+    }
+}
@@ -0,0 +1,235 @@
+//! On-disk caches derived from the dictionary files.
+//!
+//! A cache records the modification time and the size of the files it was derived from,
+//! and is ignored once they change.
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use std::cell::RefCell;
+use std::fs::{create_dir_all, metadata, rename, write, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const IDX_MAGIC: &[u8; 8] = b"DIOXIDX2";
+
+/// The size of an item record: word start, offset, word length and size.
+const RECORD_LEN: usize = 24;
+
+/// Get the modification time and the size of each file, to tell when a cache is stale.
+pub fn file_stamp(paths: &[&Path]) -> Result<Vec<u64>> {
+    let mut stamp = Vec::with_capacity(paths.len() * 2);
+    for path in paths {
+        let meta = metadata(path).with_context(|| format!("Failed to stat {:?}", path))?;
+        let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?;
+        stamp.push(mtime.as_nanos() as u64);
+        stamp.push(meta.len());
+    }
+    Ok(stamp)
+}
+
+/// Get the path of a cache of kind `kind` for the dictionary with the given `.ifo` file,
+/// in the dioxionary cache directory.
+pub fn cache_path(ifo: &Path, kind: &str) -> Option<PathBuf> {
+    let stem = ifo.file_stem()?.to_string_lossy();
+    let hash = fnv1a(ifo.as_os_str().as_encoded_bytes());
+    let name = format!("{}-{:016x}.{}", stem, hash, kind);
+    Some(cache_dir()?.join(kind).join(name))
+}
+
+thread_local! {
+    /// The cache directory set with [set_cache_dir] for the current thread.
+    static CACHE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Keep the caches of the current thread in `dir` instead of the dioxionary cache directory.
+pub fn set_cache_dir(dir: Option<PathBuf>) {
+    CACHE_DIR.with(|cell| *cell.borrow_mut() = dir);
+}
+
+/// Get the cache directory of the current thread.
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = CACHE_DIR.with(|cell| cell.borrow().clone()) {
+        return Some(dir);
+    }
+    Some(dirs::cache_dir()?.join("dioxionary"))
+}
+
+/// Hash bytes with 64-bit FNV-1a, which unlike the standard hasher gives the same cache
+/// names across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Write a cache file atomically, so that readers never map a partly written file.
+pub fn write_cache(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+    }
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    write(&tmp, data).with_context(|| format!("Failed to write cache {:?}", tmp))?;
+    rename(&tmp, path).with_context(|| format!("Failed to write cache {:?}", path))
+}
+
+/// Read the little-endian numbers of a cache file.
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl Reader<'_> {
+    pub(crate) fn take(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.0.get(..n)?;
+        self.0 = &self.0[n..];
+        Some(bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+/// The idx items mapped from a binary cache, without parsing or copying them.
+///
+/// The file holds a header with the stamp, the number of items and the number of items
+/// dropped from the idx file, then the positions of the dropped items, a fixed-size record
+/// per item and finally the words, all numbers in little endian.
+pub struct IdxCache {
+    mmap: Mmap,
+    len: usize,
+    /// The offset of the positions of the dropped items.
+    dropped: usize,
+    /// The offset of the first record.
+    records: usize,
+    /// The offset of the words.
+    words: usize,
+}
+
+impl IdxCache {
+    /// Map a cached idx, unless it is missing, broken or stale.
+    pub fn load(path: &Path, stamp: &[u64]) -> Option<IdxCache> {
+        let file = File::open(path).ok()?;
+        // SAFETY: cache files are only replaced by renaming, never modified in place.
+        let mmap = unsafe { Mmap::map(&file) }.ok()?;
+
+        let header = IDX_MAGIC.len() + 4 + stamp.len() * 8 + 16;
+        if mmap.get(..8)? != IDX_MAGIC || mmap.len() < header {
+            return None;
+        }
+        let u64_at = |pos: usize| u64::from_le_bytes(mmap[pos..pos + 8].try_into().unwrap());
+        let stamp_len = u32::from_le_bytes(mmap[8..12].try_into().unwrap()) as usize;
+        if stamp_len != stamp.len() || (0..stamp_len).any(|i| u64_at(12 + i * 8) != stamp[i]) {
+            return None;
+        }
+        let len = u64_at(header - 16) as usize;
+        let dropped = u64_at(header - 8) as usize;
+        let records = dropped.checked_mul(8)?.checked_add(header)?;
+        let words = len.checked_mul(RECORD_LEN)?.checked_add(records)?;
+        if words > mmap.len() {
+            return None;
+        }
+        Some(IdxCache {
+            mmap,
+            len,
+            dropped: header,
+            records,
+            words,
+        })
+    }
+
+    /// Write the idx items to a cache, with the sorted positions of the items dropped
+    /// from the idx file.
+    pub fn write<S: AsRef<str>>(
+        path: &Path,
+        stamp: &[u64],
+        items: &[(S, usize, usize)],
+        dropped: &[usize],
+    ) -> Result<()> {
+        let mut data = IDX_MAGIC.to_vec();
+        data.extend((stamp.len() as u32).to_le_bytes());
+        stamp.iter().for_each(|n| data.extend(n.to_le_bytes()));
+        data.extend((items.len() as u64).to_le_bytes());
+        data.extend((dropped.len() as u64).to_le_bytes());
+        dropped
+            .iter()
+            .for_each(|&i| data.extend((i as u64).to_le_bytes()));
+
+        let mut words: Vec<u8> = Vec::new();
+        for (word, offset, size) in items {
+            let word = word.as_ref();
+            data.extend((words.len() as u64).to_le_bytes());
+            data.extend((*offset as u64).to_le_bytes());
+            data.extend((word.len() as u32).to_le_bytes());
+            data.extend((*size as u32).to_le_bytes());
+            words.extend(word.as_bytes());
+        }
+        data.extend(words);
+        write_cache(path, &data)
+    }
+
+    /// Get the number of items.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the sorted positions of the items dropped from the idx file.
+    pub fn dropped(&self) -> Vec<usize> {
+        self.mmap[self.dropped..self.records]
+            .chunks_exact(8)
+            .map(|n| u64::from_le_bytes(n.try_into().unwrap()) as usize)
+            .collect()
+    }
+
+    /// Are there no items?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the word, the offset and the size of the `i`-th item.
+    ///
+    /// A broken word reads as empty.
+    pub fn get(&self, i: usize) -> (&str, usize, usize) {
+        let record = &self.mmap[self.records + i * RECORD_LEN..][..RECORD_LEN];
+        let start = u64::from_le_bytes(record[..8].try_into().unwrap()) as usize;
+        let offset = u64::from_le_bytes(record[8..16].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(record[16..20].try_into().unwrap()) as usize;
+        let size = u32::from_le_bytes(record[20..].try_into().unwrap()) as usize;
+        let word = start
+            .checked_add(self.words)
+            .and_then(|start| self.mmap.get(start..start.checked_add(len)?))
+            .and_then(|word| std::str::from_utf8(word).ok())
+            .unwrap_or_default();
+        (word, offset, size)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{set_cache_dir, IdxCache};
+    use tempfile::TempDir;
+
+    /// Create a temporary directory and keep the caches of the current thread in it.
+    pub(crate) fn tempdir() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        set_cache_dir(Some(dir.path().join("cache")));
+        dir
+    }
+
+    #[test]
+    fn map_cached_idx() {
+        let items = [("a", 0, 3), ("bé", 3, 70000), ("中文", 1 << 40, 1)];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.idx");
+        IdxCache::write(&path, &[1, 2], &items, &[1, 4]).unwrap();
+
+        assert!(IdxCache::load(&path, &[1, 3]).is_none());
+        assert!(IdxCache::load(&path, &[1]).is_none());
+        let cache = IdxCache::load(&path, &[1, 2]).unwrap();
+        assert_eq!(cache.len(), items.len());
+        assert_eq!(cache.dropped(), [1, 4]);
+        for (i, item) in items.iter().enumerate() {
+            assert_eq!(cache.get(i), *item);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 //! Dioxionary command line parameters.
+use crate::datadir::DataDirs;
+use crate::fuzzy::Ranking;
+use crate::QueryOptions;
 pub use clap::{Args, Parser};
 use clap_complete::Shell;
+use std::path::PathBuf;
 
 /// Dioxionary command line parameters.
 #[derive(Parser, Debug)]
@@ -15,6 +19,32 @@ use clap_complete::Shell;
     dioxionary count
   you can list all dictionaries:
     dioxionary dicts
+  you can disable, enable, reorder and alias dictionaries:
+    dioxionary dicts disable <NAME>...
+    dioxionary dicts order <NAME>...
+    dioxionary dicts alias <NAME> <ALIAS>
+  you can show the details of dictionaries:
+    dioxionary dicts --long
+    dioxionary info <NAME>
+  you can build a dictionary from a glossary:
+    dioxionary build glossary.tsv -o ~/.config/dioxionary/glossary
+  you can list, extract or play the resources of a dictionary:
+    dioxionary res <NAME>
+    dioxionary res <NAME> -o <DIR> [KEY]...
+    dioxionary res <NAME> --play <KEY>
+  you can print or browse a tree dictionary:
+    dioxionary tree <NAME> --depth 2
+    dioxionary tree <NAME> --node <WORD>
+    dioxionary tree <NAME> --browse
+  you can check dictionaries for defects:
+    dioxionary dicts verify [NAME]...
+  you can show the entries of all dictionaries:
+    dioxionary --scope all rust
+  you can search headwords by prefix, glob or regex:
+    dioxionary search 'colo*r'
+  you can search dictionaries in another data directory first, or only there:
+    dioxionary --data-dir ~/dicts rust
+    dioxionary --data-dir ~/dicts --only-data-dir dicts
 "
 )]
 pub struct Cli {
@@ -22,7 +52,7 @@ pub struct Cli {
     #[command(subcommand)]
     pub action: Option<Action>,
 
-    /// Specify local dictionary.
+    /// Specify local dictionary by path, name or alias.
     #[arg(short, long)]
     pub local: Option<String>,
 
@@ -42,6 +72,26 @@ pub struct Cli {
     #[arg(short, long, default_value_t = false)]
     pub read_aloud: bool,
 
+    /// How to rank the suggestions of fuzzy search.
+    #[arg(long, value_enum, default_value_t = Rank::Balanced)]
+    pub rank: Rank,
+
+    /// Show the entries of the first or of all dictionaries containing the word.
+    #[arg(long, value_enum, default_value_t = Scope::First)]
+    pub scope: Scope,
+
+    /// Only search the dictionary with this name, can be repeated.
+    #[arg(short = 'd', long = "dict", value_name = "NAME")]
+    pub dicts: Vec<String>,
+
+    /// Search dictionaries in this data directory first, can be repeated.
+    #[arg(long = "data-dir", global = true, value_name = "DIR")]
+    pub data_dirs: Vec<PathBuf>,
+
+    /// Only search dictionaries in the data directories given by `--data-dir`.
+    #[arg(long, global = true, default_value_t = false, requires = "data_dirs")]
+    pub only_data_dir: bool,
+
     /// Generate shell completion scripts.
     #[arg(short, long, value_enum, value_name = "SHELL")]
     pub completions: Option<Shell>,
@@ -62,14 +112,29 @@ pub enum Action {
     /// Count the number of each type.
     Count,
 
-    /// Display list of available dictionaries and exit.
-    Dicts,
+    /// Display list of available dictionaries and exit, or configure them.
+    Dicts(Dicts),
+
+    /// List the headwords matching a pattern in all dictionaries.
+    Search(Search),
+
+    /// Show the metadata and the files of a dictionary.
+    Info(Info),
+
+    /// Build a dictionary from a tab-separated, CSV or JSON Lines glossary.
+    Build(Build),
+
+    /// List, extract or play the resources of a dictionary, such as pictures and sounds.
+    Res(Res),
+
+    /// Print or browse the hierarchy of a tree dictionary.
+    Tree(Tree),
 }
 
 /// Subcommand line parameters for looking up words.
 #[derive(Args, Debug)]
 pub struct Lookup {
-    /// Specify local dictionary.
+    /// Specify local dictionary by path, name or alias.
     #[arg(short, long)]
     pub local: Option<String>,
 
@@ -89,10 +154,232 @@ pub struct Lookup {
     #[arg(short, long, default_value_t = false)]
     pub read_aloud: bool,
 
+    /// How to rank the suggestions of fuzzy search.
+    #[arg(long, value_enum, default_value_t = Rank::Balanced)]
+    pub rank: Rank,
+
+    /// Show the entries of the first or of all dictionaries containing the word.
+    #[arg(long, value_enum, default_value_t = Scope::First)]
+    pub scope: Scope,
+
+    /// Only search the dictionary with this name, can be repeated.
+    #[arg(short = 'd', long = "dict", value_name = "NAME")]
+    pub dicts: Vec<String>,
+
     /// The word being looked up.
     pub word: Option<Vec<String>>,
 }
 
+/// Subcommand line parameters for searching headwords.
+#[derive(Args, Debug)]
+pub struct Search {
+    /// Specify local dictionary by path, name or alias.
+    #[arg(short, long)]
+    pub local: Option<String>,
+
+    /// A prefix, a glob with `*` and `?`, or a regex after `:`.
+    pub pattern: String,
+}
+
+/// Subcommand line parameters for showing a dictionary.
+#[derive(Args, Debug)]
+pub struct Info {
+    /// Name, alias or path of the dictionary.
+    pub name: String,
+}
+
+/// Subcommand line parameters for the resources of a dictionary.
+#[derive(Args, Debug)]
+pub struct Res {
+    /// Name, alias or path of the dictionary.
+    pub name: String,
+
+    /// Keys of the resources, such as `snd/rust.wav`.
+    pub keys: Vec<String>,
+
+    /// Extract the resources into this directory, all of them without keys.
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// Play the resources with the given keys as sounds.
+    #[arg(short, long, default_value_t = false, conflicts_with = "output")]
+    pub play: bool,
+}
+
+/// Subcommand line parameters for a tree dictionary.
+#[derive(Args, Debug)]
+pub struct Tree {
+    /// Name, alias or path of the tree dictionary.
+    pub name: String,
+
+    /// Show the definition and the subtree of the node with this headword.
+    #[arg(short, long, value_name = "WORD")]
+    pub node: Option<String>,
+
+    /// Only print this many levels of the hierarchy.
+    #[arg(long, conflicts_with = "browse")]
+    pub depth: Option<usize>,
+
+    /// Choose the nodes interactively, one level at a time.
+    #[arg(short, long, default_value_t = false)]
+    pub browse: bool,
+}
+
+/// Subcommand line parameters for building a dictionary.
+#[derive(Args, Debug)]
+pub struct Build {
+    /// The format of the glossary, guessed from its extension by default.
+    #[arg(short, long, value_enum)]
+    pub format: Option<SourceFormat>,
+
+    /// The directory of the dictionary, named after the glossary by default.
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// The name of the dictionary, the name of the glossary by default.
+    #[arg(long)]
+    pub bookname: Option<String>,
+
+    #[arg(long)]
+    pub author: Option<String>,
+
+    #[arg(long)]
+    pub description: Option<String>,
+
+    /// The glossary, one entry per line.
+    pub input: PathBuf,
+}
+
+/// Formats of the glossaries to build dictionaries from.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// Tab-separated headwords and definitions, with `\n`, `\t` and `\\` escapes.
+    Tsv,
+    /// Comma-separated headwords and definitions.
+    Csv,
+    /// JSON objects with `word`, `definition` and optional `synonyms`.
+    Jsonl,
+}
+
+/// Ranking presets of fuzzy search suggestions.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Rank {
+    /// By the edit distance only.
+    Distance,
+    /// Typing mistakes first: transpositions, neighbouring keys and a wrong ending.
+    Typo,
+    /// By edit distance, typing mistakes, entry length as a hint of how common a word is,
+    /// and history.
+    Balanced,
+    /// Words already looked up first.
+    History,
+}
+
+impl From<Rank> for Ranking {
+    fn from(rank: Rank) -> Ranking {
+        match rank {
+            Rank::Distance => Ranking::DISTANCE,
+            Rank::Typo => Ranking::TYPO,
+            Rank::Balanced => Ranking::BALANCED,
+            Rank::History => Ranking::HISTORY,
+        }
+    }
+}
+
+/// Subcommand line parameters for configuring dictionaries.
+#[derive(Args, Debug)]
+pub struct Dicts {
+    #[command(subcommand)]
+    pub action: Option<DictsAction>,
+
+    /// Also show the version, the synonym count and the file formats.
+    #[arg(long, default_value_t = false)]
+    pub long: bool,
+}
+
+/// Dictionary configuration subcommands, dictionaries are given by name or alias.
+#[derive(clap::Subcommand, Debug)]
+pub enum DictsAction {
+    /// Search the dictionaries again.
+    Enable {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Stop searching the dictionaries, unless they are specified.
+    Disable {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Search the dictionaries first, in this order.
+    Order {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Give the dictionary a short alias, or remove it without one.
+    Alias { name: String, alias: Option<String> },
+
+    /// Check the files of the dictionaries, or of all of them, and print a JSON report.
+    Verify {
+        /// Names, aliases or paths of the dictionaries.
+        names: Vec<String>,
+    },
+}
+
+/// Which dictionaries show their entries.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// Only the first dictionary containing the word.
+    #[default]
+    First,
+    /// Every dictionary containing the word, each under its name.
+    All,
+}
+
+impl From<&Cli> for DataDirs {
+    fn from(cli: &Cli) -> DataDirs {
+        DataDirs {
+            dirs: cli.data_dirs.clone(),
+            only: cli.only_data_dir,
+            config: None,
+        }
+    }
+}
+
+impl From<&Cli> for QueryOptions {
+    fn from(cli: &Cli) -> QueryOptions {
+        QueryOptions {
+            online: cli.online,
+            local_first: cli.local_first,
+            exact: cli.exact_search,
+            path: cli.local.clone(),
+            read_aloud: cli.read_aloud,
+            ranking: cli.rank.into(),
+            scope: cli.scope,
+            dicts: cli.dicts.clone(),
+            data_dirs: cli.into(),
+        }
+    }
+}
+
+impl From<&Lookup> for QueryOptions {
+    fn from(lookup: &Lookup) -> QueryOptions {
+        QueryOptions {
+            online: lookup.online,
+            local_first: lookup.local_first,
+            exact: lookup.exact_search,
+            path: lookup.local.clone(),
+            read_aloud: lookup.read_aloud,
+            ranking: lookup.rank.into(),
+            scope: lookup.scope,
+            dicts: lookup.dicts.clone(),
+            data_dirs: DataDirs::default(),
+        }
+    }
+}
+
 /// Subcommand line parameters for listing history.
 #[derive(Args, Debug)]
 pub struct List {
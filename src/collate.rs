@@ -0,0 +1,46 @@
+//! Collation of headwords compatible with StarDict.
+//!
+//! StarDict sorts the `.idx` and `.syn` files with `g_ascii_strcasecmp`, falling back to
+//! `strcmp` for words equal under it. Only ASCII letters are folded, every other byte is
+//! compared as it is, so searches over these files must use the same ordering.
+use std::cmp::Ordering;
+
+/// Compare two words like `g_ascii_strcasecmp`, ignoring the case of ASCII letters only.
+pub fn ascii_casecmp(a: &str, b: &str) -> Ordering {
+    a.bytes()
+        .map(|c| c.to_ascii_lowercase())
+        .cmp(b.bytes().map(|c| c.to_ascii_lowercase()))
+}
+
+/// Compare the beginning of a word with a prefix like [ascii_casecmp].
+///
+/// The words starting with the prefix compare equal and are adjacent in the idx files.
+pub fn ascii_prefix_cmp(word: &str, prefix: &str) -> Ordering {
+    let head = &word.as_bytes()[..word.len().min(prefix.len())];
+    head.iter()
+        .map(|c| c.to_ascii_lowercase())
+        .cmp(prefix.bytes().map(|c| c.to_ascii_lowercase()))
+}
+
+/// Compare two words in the order of the StarDict idx files.
+pub fn stardict_strcmp(a: &str, b: &str) -> Ordering {
+    ascii_casecmp(a, b).then_with(|| a.as_bytes().cmp(b.as_bytes()))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::stardict_strcmp;
+
+    /// Headwords in the order StarDict sorts them.
+    pub(crate) const SORTED_HEADWORDS: [&str; 11] = [
+        "a-b", "Apfel", "apfel", "bar", "Bär", "Zoo", "Äpfel", "Émile", "éclair", "über", "中文",
+    ];
+
+    #[test]
+    fn sort_like_stardict() {
+        let mut words = SORTED_HEADWORDS;
+        words.reverse();
+        words.sort_by(|a, b| stardict_strcmp(a, b));
+        assert_eq!(words, SORTED_HEADWORDS);
+    }
+}
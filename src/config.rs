@@ -0,0 +1,169 @@
+//! Dictionary configuration using [sqlite](https://sqlite.org/index.html): search order,
+//! enabled state and aliases, stored in `config.db` in the dioxionary config directory.
+//!
+//! Dictionaries are identified by their bookname, so the configuration survives moving
+//! or renaming their directories.
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+
+/// The configuration of a dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictConfig {
+    /// The position in the search order, dictionaries without one come last.
+    pub position: Option<usize>,
+    pub enabled: bool,
+    pub alias: Option<String>,
+}
+
+impl Default for DictConfig {
+    fn default() -> DictConfig {
+        DictConfig {
+            position: None,
+            enabled: true,
+            alias: None,
+        }
+    }
+}
+
+/// Get the path of the configuration database, creating its directory if needed.
+fn config_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .with_context(|| "Couldn't find config directory")?
+        .join("dioxionary");
+    create_dir_all(&dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+    Ok(dir.join("config.db"))
+}
+
+/// Open the database with the dictionary configuration in the config directory.
+pub fn open() -> Result<Connection> {
+    open_at(&config_path()?)
+}
+
+/// Open the database with the dictionary configuration at `path`.
+pub fn open_at(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)
+        .with_context(|| format!("Failed to open configuration {:?}", path))?;
+    init(&conn)?;
+    Ok(conn)
+}
+
+fn init(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS DICTS (
+        NAME TEXT PRIMARY KEY,
+        POSITION INTEGER,
+        ENABLED INTEGER NOT NULL DEFAULT 1,
+        ALIAS TEXT
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Load the configuration of all the configured dictionaries by their bookname.
+pub fn load(conn: &Connection) -> Result<HashMap<String, DictConfig>> {
+    let mut stmt = conn.prepare("SELECT NAME, POSITION, ENABLED, ALIAS FROM DICTS")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            DictConfig {
+                position: row.get(1)?,
+                enabled: row.get(2)?,
+                alias: row.get(3)?,
+            },
+        ))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Enable or disable a dictionary.
+pub fn set_enabled(conn: &Connection, name: &str, enabled: bool) -> Result<()> {
+    conn.execute(
+        "INSERT INTO DICTS (NAME, ENABLED) VALUES (?1, ?2)
+        ON CONFLICT(NAME) DO UPDATE SET ENABLED = ?2",
+        (name, enabled),
+    )?;
+    Ok(())
+}
+
+/// Search the dictionaries in the order of `names`, before all the others.
+pub fn set_order(conn: &mut Connection, names: &[String]) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("UPDATE DICTS SET POSITION = NULL", ())?;
+    for (position, name) in names.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO DICTS (NAME, POSITION) VALUES (?1, ?2)
+            ON CONFLICT(NAME) DO UPDATE SET POSITION = ?2",
+            (name, position),
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Set or remove the alias of a dictionary, an alias can't be shared.
+pub fn set_alias(conn: &Connection, name: &str, alias: Option<&str>) -> Result<()> {
+    if let Some(alias) = alias {
+        let owner: Option<String> = conn
+            .query_row(
+                "SELECT NAME FROM DICTS WHERE ALIAS = ?1 COLLATE NOCASE AND NAME != ?2",
+                (alias, name),
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(owner) = owner {
+            return Err(anyhow!(
+                "The alias {:?} is already used by {}",
+                alias,
+                owner
+            ));
+        }
+    }
+    conn.execute(
+        "INSERT INTO DICTS (NAME, ALIAS) VALUES (?1, ?2)
+        ON CONFLICT(NAME) DO UPDATE SET ALIAS = ?2",
+        (name, alias),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{init, load, open_at, set_alias, set_enabled, set_order, DictConfig};
+    use rusqlite::Connection;
+
+    #[test]
+    fn configure_dicts() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+
+        set_enabled(&conn, "Oxford", false).unwrap();
+        set_alias(&conn, "Oxford", Some("ox")).unwrap();
+        assert!(set_alias(&conn, "Collins", Some("OX")).is_err());
+        set_order(&mut conn, &["Collins".into(), "Oxford".into()]).unwrap();
+        set_order(&mut conn, &["Oxford".into()]).unwrap();
+
+        let config = load(&conn).unwrap();
+        assert_eq!(
+            config["Oxford"],
+            DictConfig {
+                position: Some(0),
+                enabled: false,
+                alias: Some("ox".into()),
+            }
+        );
+        assert_eq!(config["Collins"], DictConfig::default());
+    }
+
+    #[test]
+    fn keep_config_in_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.db");
+        set_alias(&open_at(&path).unwrap(), "Oxford", Some("ox")).unwrap();
+        let config = load(&open_at(&path).unwrap()).unwrap();
+        assert_eq!(config["Oxford"].alias.as_deref(), Some("ox"));
+    }
+}
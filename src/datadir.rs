@@ -0,0 +1,190 @@
+//! Data directories holding the stardicts, resolved like sdcv.
+//!
+//! The directories given on the command line come first, then the dioxionary config
+//! directory, `~/.stardict/dic` and `$STARDICT_DATA_DIR/dic` or `/usr/share/stardict/dic`.
+//! Every directory containing an `.ifo` file under them is a stardict, every one
+//! containing an `.index` file is a dictd dictionary, every one containing an `.mdx`
+//! file is an MDict dictionary and every one containing a `.dsl` or `.dsl.dz` file is a
+//! DSL dictionary.
+use crate::dsl;
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::env;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+
+/// The data directories to search for dictionaries.
+#[derive(Debug, Clone, Default)]
+pub struct DataDirs {
+    /// Extra data directories, searched first.
+    pub dirs: Vec<PathBuf>,
+    /// Search only the extra data directories?
+    pub only: bool,
+    /// The configuration database, `config.db` in the dioxionary config directory if unset.
+    pub config: Option<PathBuf>,
+}
+
+impl DataDirs {
+    /// Get the existing data directories in the search order.
+    pub fn roots(&self) -> Vec<PathBuf> {
+        // Like sdcv, a data directory may keep its stardicts in a `dic` subdirectory.
+        let mut roots: Vec<_> = self
+            .dirs
+            .iter()
+            .map(|dir| {
+                Some(dir.join("dic"))
+                    .filter(|dic| dic.is_dir())
+                    .unwrap_or(dir.clone())
+            })
+            .collect();
+        if !self.only {
+            roots.extend(dirs::config_dir().map(|dir| dir.join("dioxionary")));
+            roots.extend(dirs::home_dir().map(|dir| dir.join(".stardict").join("dic")));
+            roots.push(match env::var_os("STARDICT_DATA_DIR") {
+                Some(dir) => Path::new(&dir).join("dic"),
+                None => PathBuf::from("/usr/share/stardict/dic"),
+            });
+        }
+        roots.retain(|dir| dir.is_dir());
+        roots
+    }
+
+    /// Find the dictionary directories, each dictionary only once.
+    pub fn find_dicts(&self) -> Result<Vec<PathBuf>> {
+        let roots = self.roots();
+        if roots.is_empty() {
+            return Err(anyhow!("Couldn't find configuration directory"));
+        }
+
+        let mut visited = HashSet::new();
+        let mut dicts = Vec::new();
+        for root in roots {
+            find_dict_dirs(&root, &mut visited, &mut dicts);
+        }
+
+        // The same dictionary may be installed in several data directories. The ifo files
+        // are compared, the larger files only by name and size.
+        let mut seen = HashSet::new();
+        dicts.retain(|dir| {
+            dict_file(dir)
+                .and_then(
+                    |file| match file.extension().is_some_and(|ext| ext == "ifo") {
+                        true => read(file).ok(),
+                        false => {
+                            let len = file.metadata().ok()?.len();
+                            Some(format!("{:?}:{}", file.file_name()?, len).into_bytes())
+                        }
+                    },
+                )
+                .is_some_and(|key| seen.insert(key))
+        });
+        Ok(dicts)
+    }
+}
+
+/// Get the `.ifo` file of a stardict, the `.index` file of a dictd dictionary, the
+/// `.mdx` file of an MDict dictionary or the DSL file of a DSL dictionary in a directory.
+fn dict_file(dir: &Path) -> Option<PathBuf> {
+    dir.read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "ifo" || ext == "index" || ext == "mdx")
+                && path.is_file()
+                || dsl::is_dsl_file(path)
+        })
+}
+
+/// Find the directories with a dictionary file under `dir` in the order of their paths.
+///
+/// `visited` holds the canonical paths already searched, following a symbolic link to
+/// a directory only once.
+fn find_dict_dirs(dir: &Path, visited: &mut HashSet<PathBuf>, found: &mut Vec<PathBuf>) {
+    let Ok(canonical) = dir.canonicalize() else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+    if dict_file(dir).is_some() {
+        found.push(dir.to_owned());
+        return;
+    }
+
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    let mut subdirs: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    subdirs.sort();
+    for subdir in subdirs {
+        find_dict_dirs(&subdir, visited, found);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DataDirs;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn find_nested_dicts_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let ifo = |dir: &str, name: &str| {
+            create_dir_all(root.join(dir)).unwrap();
+            let ifo = format!(
+                "StarDict's dict ifo file\nversion=2.4.2\nbookname={}\n",
+                name
+            );
+            write(root.join(dir).join(format!("{}.ifo", name)), ifo).unwrap();
+        };
+        ifo("first/dic/b-oxford", "oxford");
+        ifo("first/dic/a-collins", "collins");
+        ifo("first/dic/en/medical", "medical");
+        ifo("second/oxford", "oxford");
+        ifo("second/wordnet", "wordnet");
+        create_dir_all(root.join("second/empty")).unwrap();
+        create_dir_all(root.join("second/jargon")).unwrap();
+        write(root.join("second/jargon/jargon.index"), "hack\tA\tB\n").unwrap();
+
+        let data_dirs = DataDirs {
+            dirs: vec![
+                root.join("first"),
+                root.join("second"),
+                root.join("first/dic"),
+            ],
+            only: true,
+            ..Default::default()
+        };
+        let dicts: Vec<_> = data_dirs
+            .find_dicts()
+            .unwrap()
+            .into_iter()
+            .map(|dir| dir.strip_prefix(root).unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            dicts,
+            [
+                "first/dic/a-collins",
+                "first/dic/b-oxford",
+                "first/dic/en/medical",
+                "second/jargon",
+                "second/wordnet"
+            ]
+            .map(std::path::PathBuf::from)
+        );
+
+        let data_dirs = DataDirs {
+            dirs: vec![root.join("missing")],
+            only: true,
+            ..Default::default()
+        };
+        assert!(data_dirs.find_dicts().is_err());
+    }
+}
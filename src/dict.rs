@@ -173,22 +173,30 @@ impl fmt::Display for WordItem {
 
 /// Play word pronunciation.
 pub fn read_aloud(word: &str) -> Result<()> {
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
     let url = format!("https://dict.youdao.com/dictvoice?audio={}&type=1", word);
     let response = reqwest::blocking::get(url)?;
-    let inner = response.bytes()?;
-    if let Ok(source) = Decoder::new(Cursor::new(inner)) {
-        if let Ok(sink) = Sink::try_new(&stream_handle) {
-            sink.append(source);
-            sink.sleep_until_end();
-        }
-    }
+    play(response.bytes()?.to_vec())
+}
+
+/// Play a sound file, such as a wav or mp3 file, until its end.
+pub fn play(sound: Vec<u8>) -> Result<()> {
+    let source = Decoder::new(Cursor::new(sound)).with_context(|| "Failed to decode the sound")?;
+    let (_stream, stream_handle) =
+        OutputStream::try_default().with_context(|| "Failed to open the audio device")?;
+    let sink = Sink::try_new(&stream_handle).with_context(|| "Failed to play the sound")?;
+    sink.append(source);
+    sink.sleep_until_end();
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use super::WordItem;
+    use super::{play, WordItem};
+
+    #[test]
+    fn play_undecodable_sound() {
+        assert!(play(b"not a sound".to_vec()).is_err());
+    }
 
     #[test]
     fn lookup_online_by_english() {
@@ -0,0 +1,460 @@
+//! Look up words from dictd dictionaries, such as those of FreeDict, WordNet or GCIDE.
+//!
+//! A dictd dictionary is an `.index` file with a line per entry: the headword, then the
+//! offset and the size of its definition in the `.dict` or `.dict.dz` file, written in
+//! base64 and separated by tabs. The headwords starting with `00-database-` hold the
+//! metadata, such as the name of the dictionary in `00-database-short`.
+//!
+//! The entries are sorted by headword ignoring the case of ASCII letters when the index is
+//! first read, and cached like a stardict idx.
+use crate::collate::ascii_prefix_cmp;
+use crate::fulltext::FullTextIndex;
+use crate::fuzzy::{BkTree, Ranking};
+use crate::pattern::Pattern;
+use crate::stardict::{partition_point, Dict, Entry, EntryPart, Idx, IdxItem, Problem, Report};
+use anyhow::{anyhow, Context, Result};
+use std::cell::OnceCell;
+use std::collections::HashSet;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+
+/// A dictd dictionary.
+pub struct Dictd {
+    index_file: PathBuf,
+    dict_file: PathBuf,
+    /// The index and the dict file, loaded on first use.
+    data: OnceCell<Data>,
+    fuzzy_index: OnceCell<BkTree>,
+    fulltext_index: OnceCell<FullTextIndex>,
+}
+
+/// The searchable contents of a dictd dictionary.
+struct Data {
+    /// The name from `00-database-short`.
+    name: Option<String>,
+    /// The headword, the offset and the size of each entry, sorted by [Idx::sort_folded].
+    idx: Idx,
+    /// The number of the metadata entries.
+    metadata: usize,
+    dict: Dict,
+}
+
+/// Find the files in a directory with a name ending with `suffix`.
+fn find_file(dir: &Path, suffix: &str) -> Option<PathBuf> {
+    dir.read_dir()
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().ends_with(suffix))
+        })
+}
+
+/// Is the directory a dictd dictionary?
+pub fn is_dictd(dir: &Path) -> bool {
+    find_file(dir, ".index").is_some()
+}
+
+/// Decode a number written in the base64 digits of dictd, the most significant first.
+fn decode_number(digits: &str) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.bytes().try_fold(0usize, |n, b| {
+        let digit = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        n.checked_mul(64)?.checked_add(digit as usize)
+    })
+}
+
+/// Is the headword one of the metadata entries, such as `00-database-short`?
+fn is_metadata(word: &str) -> bool {
+    word.starts_with("00-database-") || word.starts_with("00database")
+}
+
+/// Parse the lines of an index file into headwords, offsets and sizes.
+fn parse_index(data: &[u8]) -> Result<Vec<IdxItem>> {
+    data.split(|&b| b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            let line = String::from_utf8_lossy(line);
+            let mut fields = line.trim_end_matches('\r').split('\t');
+            let word = fields.next().unwrap_or_default();
+            let mut number = || fields.next().and_then(decode_number);
+            match (number(), number()) {
+                (Some(offset), Some(size)) => Ok((word.to_owned(), offset, size)),
+                _ => Err(anyhow!("Invalid index line {}: {:?}", i + 1, line)),
+            }
+        })
+        .collect()
+}
+
+/// Get the text of the first metadata entry found among `names`, without the headword
+/// which dictfmt repeats at its beginning.
+fn metadata_text(idx: &Idx, dict: &Dict, names: &[&str]) -> Option<String> {
+    let (word, offset, size) = names
+        .iter()
+        .find_map(|name| idx.folded_range(name).next())
+        .map(|i| idx.get(i))?;
+    let text = String::from_utf8_lossy(&dict.get(offset, size).ok()?).into_owned();
+    let text = text.trim_start();
+    let lines: Vec<_> = text
+        .strip_prefix(word)
+        .unwrap_or(text)
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty())
+        .collect();
+    Some(lines.join("\n").trim_end().to_owned()).filter(|text| !text.is_empty())
+}
+
+impl Data {
+    /// Load the index, unless it is cached, and open the dict file.
+    fn new(index_file: &Path, dict_file: &Path) -> Result<Data> {
+        let idx = Idx::cached_folded(index_file, || {
+            let data = read(index_file)
+                .with_context(|| format!("Failed to read index file {:?}", index_file))?;
+            parse_index(&data)
+                .with_context(|| format!("Failed to parse index file {:?}", index_file))
+        })?;
+        let dict = Dict::new(dict_file.to_owned())?;
+
+        // The metadata headwords all start with 00, so they are found among a few entries.
+        let start = partition_point(idx.len(), |i| ascii_prefix_cmp(idx.get(i).0, "00").is_lt());
+        let metadata = (start..idx.len())
+            .map(|i| idx.get(i).0)
+            .take_while(|word| word.starts_with("00"))
+            .filter(|word| is_metadata(word))
+            .count();
+
+        let name = metadata_text(&idx, &dict, &["00-database-short", "00databaseshort"])
+            .and_then(|text| text.lines().next().map(str::to_owned));
+        Ok(Data {
+            name,
+            idx,
+            metadata,
+            dict,
+        })
+    }
+
+    /// Get the positions of the entries which aren't metadata.
+    fn words(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.idx.len()).filter(|&i| !is_metadata(self.idx.get(i).0))
+    }
+}
+
+impl Dictd {
+    /// Open a dictd dictionary in a directory, its index being read on first use.
+    pub fn new(path: &Path) -> Result<Dictd> {
+        let index_file = find_file(path, ".index")
+            .with_context(|| format!("Missing .index file in {:?}", path))?;
+        let dict_file = find_file(path, ".dict")
+            .or_else(|| find_file(path, ".dict.dz"))
+            .with_context(|| format!("Missing .dict or .dict.dz file in {:?}", path))?;
+        Ok(Dictd {
+            index_file,
+            dict_file,
+            data: OnceCell::new(),
+            fuzzy_index: OnceCell::new(),
+            fulltext_index: OnceCell::new(),
+        })
+    }
+
+    /// Get the contents of the dictionary, loading them on first use.
+    fn data(&self) -> Result<&Data> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = Data::new(&self.index_file, &self.dict_file)?;
+        Ok(self.data.get_or_init(|| data))
+    }
+
+    /// Build the entry of the `index`-th item.
+    fn entry(&self, index: usize) -> Result<Entry<'_>> {
+        let Data { idx, dict, .. } = self.data()?;
+        let (word, offset, size) = idx.get(index);
+        let data = dict
+            .get(offset, size)
+            .with_context(|| format!("Failed to read the definition of {:?}", word))?;
+        let trans = String::from_utf8_lossy(&data).trim_end().to_owned();
+        Ok(Entry {
+            word,
+            parts: vec![EntryPart::Meaning(trans.clone())],
+            trans,
+            synonym: None,
+        })
+    }
+
+    /// Get the name of the dictionary, from `00-database-short` or the index file.
+    pub fn dict_name(&self) -> &str {
+        match self.data().ok().and_then(|data| data.name.as_deref()) {
+            Some(name) => name,
+            None => self
+                .index_file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Get the directory of the dictionary.
+    pub fn path(&self) -> &Path {
+        self.index_file.parent().unwrap_or(&self.index_file)
+    }
+
+    /// Get the description, from `00-database-info`.
+    pub fn description(&self) -> String {
+        self.metadata_text(&["00-database-info", "00databaseinfo"])
+    }
+
+    /// Get the website, from `00-database-url`.
+    pub fn website(&self) -> String {
+        self.metadata_text(&["00-database-url", "00databaseurl"])
+    }
+
+    /// Get the text of the first metadata entry found among `names`, empty if the
+    /// dictionary can't be read.
+    fn metadata_text(&self, names: &[&str]) -> String {
+        let Ok(Data { idx, dict, .. }) = self.data() else {
+            return String::new();
+        };
+        metadata_text(idx, dict, names).unwrap_or_default()
+    }
+
+    /// Get the number of the entries, without the metadata, 0 if the index can't be read.
+    pub fn wordcount(&self) -> usize {
+        self.data().map_or(0, |data| data.idx.len() - data.metadata)
+    }
+
+    /// Get the index and dict files.
+    pub fn files(&self) -> Vec<&Path> {
+        vec![&self.index_file, &self.dict_file]
+    }
+
+    /// Look up all the entries of a word, ignoring the case of ASCII letters like dictd.
+    pub fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        if is_metadata(word) {
+            return Ok(Vec::new());
+        }
+        let idx = &self.data()?.idx;
+        idx.folded_range(word).map(|i| self.entry(i)).collect()
+    }
+
+    /// Find the headwords matching a glob or a regular expression, each once, sorted
+    /// ignoring the case of ASCII letters.
+    ///
+    /// Globs ignore the case of ASCII letters, regular expressions don't.
+    pub fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        let data = self.data()?;
+        let mut seen = HashSet::new();
+        Ok(data
+            .words()
+            .map(|i| data.idx.get(i).0)
+            .filter(|word| pattern.is_match(word) && seen.insert(*word))
+            .collect())
+    }
+
+    /// Look up a word with fuzzy searching enabled, ordering the entries by `ranking`.
+    pub fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        let idx = &self.data()?.idx;
+        self.fuzzy_index()?
+            .search_ranked(word, ranking, history, |i| {
+                let (word, _, size) = idx.get(i);
+                Ok((word, size, i))
+            })?
+            .into_iter()
+            .map(|i| self.entry(i))
+            .collect()
+    }
+
+    /// Get the fuzzy searching index, loading it from the cache or building it on first use.
+    fn fuzzy_index(&self) -> Result<&BkTree> {
+        let file = &self.index_file;
+        BkTree::cached(&self.fuzzy_index, file, &[file], || {
+            let data = self.data()?;
+            Ok(data.words().map(|i| (data.idx.get(i).0, i)))
+        })
+    }
+
+    /// Find the entries whose definition contains `phrase`, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// Returns at most [FULLTEXT_LIMIT](crate::stardict::FULLTEXT_LIMIT) entries in the order
+    /// of their headwords.
+    pub fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        self.fulltext_index()?
+            .search(phrase, |doc| self.entry(doc).map(Some))
+    }
+
+    /// Get the full-text index, loading it from the cache or building it on first use.
+    fn fulltext_index(&self) -> Result<&FullTextIndex> {
+        let (index_file, dict_file) = (&self.index_file, &self.dict_file);
+        let files = [index_file.as_path(), dict_file];
+        FullTextIndex::cached(
+            &self.fulltext_index,
+            self.dict_name(),
+            index_file,
+            &files,
+            || {
+                let docs = self
+                    .data()?
+                    .words()
+                    .filter_map(|i| Some((i as u32, self.entry(i).ok()?.trans)));
+                Ok(docs)
+            },
+        )
+    }
+
+    /// Check that the index can be read and the dict file opened, which also reads the
+    /// metadata.
+    ///
+    /// See [Dictd::verify] for a full check.
+    pub fn check(&self) -> Result<()> {
+        self.data()?;
+        Ok(())
+    }
+
+    /// Check that the index can be read and that every definition lies in the dict file.
+    pub fn verify(&self) -> Report {
+        let mut problems = Vec::new();
+        match self.data() {
+            Ok(data) => verify_data(data, &mut problems),
+            Err(e) => problems.push(Problem::new("index", format!("{:#}", e))),
+        }
+        Report {
+            path: self.path().to_owned(),
+            bookname: Some(self.dict_name().to_owned()),
+            problems,
+        }
+    }
+}
+
+/// Check that every definition lies in the dict file.
+fn verify_data(data: &Data, problems: &mut Vec<Problem>) {
+    let Data { idx, dict, .. } = data;
+    let len = dict.len();
+    let items: Vec<_> = data
+        .words()
+        .filter(|&i| {
+            let (_, offset, size) = idx.get(i);
+            offset.checked_add(size).is_none_or(|end| end > len)
+        })
+        .collect();
+    if let Some(&first) = items.first() {
+        problems.push(Problem::with_items(
+            "out_of_range",
+            format!(
+                "{} definitions are out of the dict file, the first is {:?}",
+                items.len(),
+                idx.get(first).0
+            ),
+            items,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_number, is_dictd, Dictd};
+    use crate::cache::test::tempdir;
+    use crate::fuzzy::Ranking;
+    use crate::pattern::Pattern;
+    use crate::stardict::Idx;
+    use std::collections::HashSet;
+    use std::fs::write;
+
+    /// Write the base64 digits of dictd.
+    fn encode_number(mut n: usize) -> String {
+        const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut digits = vec![DIGITS[n % 64]];
+        while n >= 64 {
+            n /= 64;
+            digits.push(DIGITS[n % 64]);
+        }
+        digits.reverse();
+        String::from_utf8(digits).unwrap()
+    }
+
+    #[test]
+    fn read_dictd() {
+        assert_eq!(decode_number("A"), Some(0));
+        assert_eq!(decode_number("BA"), Some(64));
+        assert_eq!(decode_number("c2"), Some(28 * 64 + 54));
+        assert_eq!(decode_number(&encode_number(123456)), Some(123456));
+        assert_eq!(decode_number("A-"), None);
+
+        let dir = tempdir();
+        let dir = dir.path();
+        let entries = [
+            ("00-database-short", "00-database-short\n     Test Dictd\n"),
+            ("00-database-url", "https://example.org\n"),
+            ("Rust", "Rust\n  a programming language\n"),
+            ("rust", "rust\n  iron oxide\n"),
+            ("rusty", "rusty\n  covered with rust\n"),
+        ];
+        let (mut index, mut dict) = (String::new(), String::new());
+        for (word, definition) in entries {
+            index += &format!(
+                "{}\t{}\t{}\n",
+                word,
+                encode_number(dict.len()),
+                encode_number(definition.len())
+            );
+            dict += definition;
+        }
+        write(dir.join("test.index"), index).unwrap();
+        write(dir.join("test.dict"), dict).unwrap();
+
+        assert!(is_dictd(dir));
+        let d = Dictd::new(dir).unwrap();
+        assert_eq!(d.dict_name(), "Test Dictd");
+        assert_eq!(d.website(), "https://example.org");
+        assert_eq!(d.description(), "");
+        assert_eq!(d.wordcount(), 3);
+        assert!(d.verify().problems.is_empty());
+
+        let entries = d.exact_lookup_all("RUST").unwrap();
+        let trans: Vec<_> = entries.iter().map(|e| e.trans.as_str()).collect();
+        assert_eq!(
+            trans,
+            ["Rust\n  a programming language", "rust\n  iron oxide"]
+        );
+        assert!(d.exact_lookup_all("00-database-url").unwrap().is_empty());
+
+        // The index is read on first use, from the cache the second time.
+        let d = Dictd::new(dir).unwrap();
+        assert!(d.data.get().is_none());
+        assert!(d.check().is_ok());
+        assert!(matches!(d.data().unwrap().idx, Idx::Cached(_)));
+        assert_eq!(d.dict_name(), "Test Dictd");
+        assert_eq!(d.wordcount(), 3);
+        assert_eq!(d.exact_lookup_all("rust").unwrap().len(), 2);
+
+        let pattern = Pattern::parse("rust?").unwrap().unwrap();
+        assert_eq!(d.pattern_search(&pattern).unwrap(), ["rusty"]);
+        let entries = d
+            .fuzzy_lookup_ranked("rsuty", &Ranking::TYPO, &HashSet::new())
+            .unwrap();
+        assert_eq!(entries[0].word, "rusty");
+
+        write(dir.join("test.index"), "rust\tA\n").unwrap();
+        let d = Dictd::new(dir).unwrap();
+        assert!(d.check().is_err());
+        assert!(d.exact_lookup_all("rust").is_err());
+        assert_eq!(d.dict_name(), "test");
+        assert_eq!(d.verify().problems[0].kind, "index");
+    }
+}
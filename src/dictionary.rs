@@ -0,0 +1,453 @@
+//! The offline dictionaries behind one interface, whatever their format.
+use crate::dictd::{self, Dictd};
+use crate::dsl::{self, Dsl};
+use crate::fuzzy::Ranking;
+use crate::mdict::{self, MDict};
+use crate::pattern::Pattern;
+use crate::stardict::{Entry, Report, StarDict};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// An offline dictionary to look up words in, see [StarDict], [Dictd], [MDict] and [Dsl].
+pub trait Dictionary {
+    /// Get the name of the dictionary.
+    fn dict_name(&self) -> &str;
+
+    /// Get the directory of the dictionary.
+    fn path(&self) -> &Path;
+
+    /// Get the number of the words.
+    fn wordcount(&self) -> usize;
+
+    /// Get the number of the synonyms.
+    fn synwordcount(&self) -> usize {
+        0
+    }
+
+    /// Get the name and the version of the format.
+    fn version(&self) -> String;
+
+    /// Get the problems tolerated while opening the dictionary, for the caller to report.
+    fn warnings(&self) -> &[String] {
+        &[]
+    }
+
+    /// Describe the files, such as `idx.gz, dict.dz, 64-bit offsets`.
+    fn details(&self) -> String;
+
+    /// Get the files of the dictionary.
+    fn files(&self) -> Vec<&Path>;
+
+    /// Get the description, where `<br>` may stand for a line break.
+    fn description(&self) -> String {
+        String::new()
+    }
+
+    /// Get the other metadata, such as the author or the website, as names and values.
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    /// Check cheaply that the dictionary is not broken, without reading all its contents.
+    fn check(&self) -> Result<()>;
+
+    /// Check the files of the dictionary for defects.
+    fn verify(&self) -> Report;
+
+    /// Look up all the entries of a word with fuzzy searching disabled.
+    fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>>;
+
+    /// Find the headwords matching a glob or a regular expression.
+    fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>>;
+
+    /// Look up a word with fuzzy searching enabled, ordering the entries by `ranking`.
+    fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>>;
+
+    /// Find the entries whose definition contains `phrase`.
+    fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>>;
+
+    /// Get the sound of an entry, if the dictionary has any.
+    fn sound(&self, _entry: &Entry) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Read the resource with this key, such as `img/rust.png`, `None` if it is missing.
+    fn resource(&self, _key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Get the keys of all the resources, sorted.
+    fn resource_keys(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+/// Open the dictionary in a directory in the format of its files.
+pub fn open(path: &Path) -> Result<Box<dyn Dictionary>> {
+    if dictd::is_dictd(path) {
+        Ok(Box::new(Dictd::new(path)?))
+    } else if mdict::is_mdict(path) {
+        Ok(Box::new(MDict::new(path)?))
+    } else if dsl::is_dsl(path) {
+        Ok(Box::new(Dsl::new(path)?))
+    } else {
+        Ok(Box::new(StarDict::new(path.to_owned())?))
+    }
+}
+
+impl Dictionary for StarDict {
+    fn dict_name(&self) -> &str {
+        StarDict::dict_name(self)
+    }
+
+    fn path(&self) -> &Path {
+        StarDict::path(self)
+    }
+
+    fn wordcount(&self) -> usize {
+        StarDict::wordcount(self)
+    }
+
+    fn synwordcount(&self) -> usize {
+        StarDict::synwordcount(self)
+    }
+
+    fn version(&self) -> String {
+        format!("stardict {}", StarDict::version(self))
+    }
+
+    fn warnings(&self) -> &[String] {
+        StarDict::warnings(self)
+    }
+
+    fn details(&self) -> String {
+        let files = self.files();
+        let mut details = vec![
+            if files[1].extension().is_some_and(|ext| ext == "gz") {
+                "idx.gz"
+            } else {
+                "idx"
+            }
+            .to_owned(),
+            if files[2].extension().is_some_and(|ext| ext == "dz") {
+                "dict.dz"
+            } else {
+                "dict"
+            }
+            .to_owned(),
+        ];
+        if files.len() > 3 {
+            details.push("syn".to_owned());
+        }
+        if let Ok(bits) = self.idx_offset_bits() {
+            details.push(format!("{}-bit offsets", bits));
+        }
+        if !self.sametypesequence().is_empty() {
+            details.push(format!("sametypesequence={}", self.sametypesequence()));
+        }
+        details.join(", ")
+    }
+
+    fn files(&self) -> Vec<&Path> {
+        StarDict::files(self)
+    }
+
+    fn description(&self) -> String {
+        StarDict::description(self).to_owned()
+    }
+
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Author", self.author().to_owned()),
+            ("Email", self.email().to_owned()),
+            ("Website", self.website().to_owned()),
+            ("Date", self.date().to_owned()),
+            ("Type", self.dicttype().to_owned()),
+        ]
+    }
+
+    fn check(&self) -> Result<()> {
+        StarDict::check(self)
+    }
+
+    fn verify(&self) -> Report {
+        StarDict::verify(self)
+    }
+
+    fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        StarDict::exact_lookup_all(self, word)
+    }
+
+    fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        StarDict::pattern_search(self, pattern)
+    }
+
+    fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        StarDict::fuzzy_lookup_ranked(self, word, ranking, history)
+    }
+
+    fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        StarDict::fulltext_search(self, phrase)
+    }
+
+    fn sound(&self, entry: &Entry) -> Result<Option<Vec<u8>>> {
+        StarDict::sound(self, entry)
+    }
+
+    fn resource(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        StarDict::resource(self, key)
+    }
+
+    fn resource_keys(&self) -> Result<Vec<String>> {
+        StarDict::resource_keys(self)
+    }
+}
+
+impl Dictionary for Dictd {
+    fn dict_name(&self) -> &str {
+        Dictd::dict_name(self)
+    }
+
+    fn path(&self) -> &Path {
+        Dictd::path(self)
+    }
+
+    fn wordcount(&self) -> usize {
+        Dictd::wordcount(self)
+    }
+
+    fn version(&self) -> String {
+        "dictd".to_owned()
+    }
+
+    fn details(&self) -> String {
+        let dict = self.files()[1];
+        if dict.extension().is_some_and(|ext| ext == "dz") {
+            "index, dict.dz".to_owned()
+        } else {
+            "index, dict".to_owned()
+        }
+    }
+
+    fn files(&self) -> Vec<&Path> {
+        Dictd::files(self)
+    }
+
+    fn description(&self) -> String {
+        Dictd::description(self)
+    }
+
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![("Website", self.website())]
+    }
+
+    fn check(&self) -> Result<()> {
+        Dictd::check(self)
+    }
+
+    fn verify(&self) -> Report {
+        Dictd::verify(self)
+    }
+
+    fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        Dictd::exact_lookup_all(self, word)
+    }
+
+    fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        Dictd::pattern_search(self, pattern)
+    }
+
+    fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        Dictd::fuzzy_lookup_ranked(self, word, ranking, history)
+    }
+
+    fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        Dictd::fulltext_search(self, phrase)
+    }
+}
+
+impl Dictionary for MDict {
+    fn dict_name(&self) -> &str {
+        MDict::dict_name(self)
+    }
+
+    fn path(&self) -> &Path {
+        MDict::path(self)
+    }
+
+    fn wordcount(&self) -> usize {
+        MDict::wordcount(self)
+    }
+
+    fn version(&self) -> String {
+        format!("mdict {}", MDict::version(self))
+    }
+
+    fn warnings(&self) -> &[String] {
+        MDict::warnings(self)
+    }
+
+    fn details(&self) -> String {
+        let mut details = vec!["mdx".to_owned(), self.encoding().to_owned()];
+        match self.files().len() - 1 {
+            0 => {}
+            1 => details.push("mdd".to_owned()),
+            n => details.push(format!("{} mdd", n)),
+        }
+        details.join(", ")
+    }
+
+    fn files(&self) -> Vec<&Path> {
+        MDict::files(self)
+    }
+
+    fn description(&self) -> String {
+        MDict::description(self).to_owned()
+    }
+
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![("Date", self.date().to_owned())]
+    }
+
+    fn check(&self) -> Result<()> {
+        MDict::check(self)
+    }
+
+    fn verify(&self) -> Report {
+        MDict::verify(self)
+    }
+
+    fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        MDict::exact_lookup_all(self, word)
+    }
+
+    fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        MDict::pattern_search(self, pattern)
+    }
+
+    fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        MDict::fuzzy_lookup_ranked(self, word, ranking, history)
+    }
+
+    fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        MDict::fulltext_search(self, phrase)
+    }
+
+    fn sound(&self, entry: &Entry) -> Result<Option<Vec<u8>>> {
+        MDict::sound(self, entry)
+    }
+
+    fn resource(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        MDict::resource(self, key)
+    }
+
+    fn resource_keys(&self) -> Result<Vec<String>> {
+        MDict::resource_keys(self)
+    }
+}
+
+impl Dictionary for Dsl {
+    fn dict_name(&self) -> &str {
+        Dsl::dict_name(self)
+    }
+
+    fn path(&self) -> &Path {
+        Dsl::path(self)
+    }
+
+    fn wordcount(&self) -> usize {
+        Dsl::wordcount(self)
+    }
+
+    fn version(&self) -> String {
+        "dsl".to_owned()
+    }
+
+    fn warnings(&self) -> &[String] {
+        Dsl::warnings(self)
+    }
+
+    fn details(&self) -> String {
+        let mut details = vec![
+            if self.file().extension().is_some_and(|ext| ext == "dz") {
+                "dsl.dz"
+            } else {
+                "dsl"
+            }
+            .to_owned(),
+            self.encoding().to_owned(),
+        ];
+        match (
+            self.header("INDEX_LANGUAGE"),
+            self.header("CONTENTS_LANGUAGE"),
+        ) {
+            ("", "") => {}
+            (from, to) => details.push(format!("{}-{}", from, to)),
+        }
+        details.join(", ")
+    }
+
+    fn files(&self) -> Vec<&Path> {
+        vec![self.file()]
+    }
+
+    fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Index language", self.header("INDEX_LANGUAGE").to_owned()),
+            (
+                "Contents language",
+                self.header("CONTENTS_LANGUAGE").to_owned(),
+            ),
+        ]
+    }
+
+    fn check(&self) -> Result<()> {
+        Dsl::check(self)
+    }
+
+    fn verify(&self) -> Report {
+        Dsl::verify(self)
+    }
+
+    fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        Dsl::exact_lookup_all(self, word)
+    }
+
+    fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        Dsl::pattern_search(self, pattern)
+    }
+
+    fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        Dsl::fuzzy_lookup_ranked(self, word, ranking, history)
+    }
+
+    fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        Dsl::fulltext_search(self, phrase)
+    }
+}
@@ -0,0 +1,314 @@
+//! Random access to [dictzip](https://linux.die.net/man/1/dictzip) compressed files.
+//!
+//! A dictzip file is a gzip file whose deflate stream is flushed every `chunk_len` bytes,
+//! with the compressed size of each chunk stored in the `RA` extra field of the header.
+//! Only the chunks covering the requested range have to be inflated.
+use anyhow::{anyhow, Context, Result};
+use flate2::{Compress, Compression, Crc, Decompress, FlushCompress, FlushDecompress};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{prelude::*, SeekFrom};
+use std::path::Path;
+use std::rc::Rc;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// The number of inflated chunks kept in memory.
+const CACHE_CHUNKS: usize = 16;
+
+/// The chunk length used by dictzip, small enough for any compressed chunk to fit in 16 bits.
+pub const CHUNK_LEN: usize = 58315;
+
+/// A dictzip file opened for random access.
+pub struct DictZip {
+    file: RefCell<File>,
+    chunk_len: usize,
+    /// The file offset and the compressed size of each chunk.
+    chunks: Vec<(u64, usize)>,
+    len: usize,
+    /// Recently inflated chunks, the most recently used first.
+    cache: RefCell<VecDeque<(usize, Rc<Vec<u8>>)>>,
+}
+
+impl DictZip {
+    /// Open a dictzip file.
+    ///
+    /// Returns `None` if the file is a plain gzip file without a chunk table.
+    pub fn open(path: &Path) -> Result<Option<DictZip>> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open dictzip file {:?}", path))?;
+        let err = || anyhow!("Broken gzip header in {:?}", path);
+
+        let mut header = [0; 10];
+        file.read_exact(&mut header).map_err(|_| err())?;
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(anyhow!("Not a gzip file {:?}", path));
+        }
+        let flags = header[3];
+        let mut pos = 10;
+
+        let mut chunk_table = None;
+        if flags & FEXTRA != 0 {
+            let xlen = read_u16(&mut file).map_err(|_| err())? as usize;
+            let mut extra = vec![0; xlen];
+            file.read_exact(&mut extra).map_err(|_| err())?;
+            pos += 2 + xlen;
+            chunk_table = parse_ra_field(&extra);
+        }
+
+        let Some((chunk_len, sizes)) = chunk_table else {
+            return Ok(None);
+        };
+
+        if flags & FNAME != 0 {
+            pos += skip_zero_terminated(&mut file).map_err(|_| err())?;
+        }
+        if flags & FCOMMENT != 0 {
+            pos += skip_zero_terminated(&mut file).map_err(|_| err())?;
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+
+        let mut offset = pos as u64;
+        let chunks = sizes
+            .into_iter()
+            .map(|size| {
+                let chunk = (offset, size);
+                offset += size as u64;
+                chunk
+            })
+            .collect();
+
+        // ISIZE in the gzip trailer is the length of the uncompressed data.
+        file.seek(SeekFrom::End(-4)).map_err(|_| err())?;
+        let mut isize = [0; 4];
+        file.read_exact(&mut isize).map_err(|_| err())?;
+        let len = u32::from_le_bytes(isize) as usize;
+
+        Ok(Some(DictZip {
+            file: RefCell::new(file),
+            chunk_len,
+            chunks,
+            len,
+            cache: RefCell::new(VecDeque::with_capacity(CACHE_CHUNKS)),
+        }))
+    }
+
+    /// Get the length of the uncompressed data.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the uncompressed data empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read `size` bytes of the uncompressed data starting at `offset`.
+    pub fn read(&self, offset: usize, size: usize) -> Result<Vec<u8>> {
+        if offset.checked_add(size).is_none_or(|end| end > self.len) {
+            return Err(anyhow!(
+                "{} bytes at offset {} are out of the dictzip data of length {}",
+                size,
+                offset,
+                self.len
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(size);
+        let mut pos = offset;
+        while pos < offset + size {
+            let chunk = self.chunk(pos / self.chunk_len)?;
+            let start = pos % self.chunk_len;
+            let end = min(chunk.len(), start + offset + size - pos);
+            if start >= end {
+                return Err(anyhow!("Chunk {} is truncated", pos / self.chunk_len));
+            }
+            buf.extend_from_slice(&chunk[start..end]);
+            pos += end - start;
+        }
+        Ok(buf)
+    }
+
+    /// Get an inflated chunk, from the cache if possible.
+    fn chunk(&self, index: usize) -> Result<Rc<Vec<u8>>> {
+        let mut cache = self.cache.borrow_mut();
+        if let Some(pos) = cache.iter().position(|(i, _)| *i == index) {
+            let entry = cache.remove(pos).unwrap();
+            let chunk = entry.1.clone();
+            cache.push_front(entry);
+            return Ok(chunk);
+        }
+
+        let chunk = Rc::new(self.inflate(index)?);
+        if cache.len() == CACHE_CHUNKS {
+            cache.pop_back();
+        }
+        cache.push_front((index, chunk.clone()));
+        Ok(chunk)
+    }
+
+    /// Inflate a chunk from the file.
+    fn inflate(&self, index: usize) -> Result<Vec<u8>> {
+        let &(offset, size) = self
+            .chunks
+            .get(index)
+            .with_context(|| format!("Chunk {} is out of the chunk table", index))?;
+
+        let mut input = vec![0; size];
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut input)
+            .with_context(|| format!("Failed to read chunk {}", index))?;
+
+        let mut output = Vec::with_capacity(self.chunk_len);
+        Decompress::new(false)
+            .decompress_vec(&input, &mut output, FlushDecompress::Sync)
+            .with_context(|| format!("Failed to inflate chunk {}", index))?;
+        Ok(output)
+    }
+}
+
+/// The most bytes a dictzip file with chunks of `chunk_len` bytes can hold, the chunk
+/// sizes having to fit in the 16-bit gzip extra field.
+pub fn max_len(chunk_len: usize) -> usize {
+    let chunks = (u16::MAX as usize - 10) / 2;
+    (chunks * chunk_len).min(u32::MAX as usize)
+}
+
+/// Compress `data` into a dictzip file with chunks of `chunk_len` bytes.
+pub fn compress(data: &[u8], chunk_len: usize) -> Result<Vec<u8>> {
+    if data.len() > max_len(chunk_len) {
+        return Err(anyhow!(
+            "{} bytes are too many for a dictzip file",
+            data.len()
+        ));
+    }
+    let mut compress = Compress::new(Compression::best(), false);
+    let mut body = Vec::new();
+    let mut sizes = Vec::new();
+    let chunks: Vec<_> = data.chunks(chunk_len).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let flush = if i + 1 == chunks.len() {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::Full
+        };
+        let mut out = Vec::with_capacity(chunk_len * 2 + 64);
+        compress.compress_vec(chunk, &mut out, flush)?;
+        sizes.push(u16::try_from(out.len()).context("A dictzip chunk is too large")?);
+        body.extend(out);
+    }
+    if chunks.is_empty() {
+        let mut out = Vec::with_capacity(64);
+        compress.compress_vec(&[], &mut out, FlushCompress::Finish)?;
+        sizes.push(out.len() as u16);
+        body.extend(out);
+    }
+
+    let len = 6 + sizes.len() * 2;
+    let xlen = (len + 4) as u16;
+    let mut file = vec![0x1f, 0x8b, 8, FEXTRA, 0, 0, 0, 0, 2, 3];
+    file.extend(xlen.to_le_bytes());
+    file.extend(b"RA");
+    file.extend((len as u16).to_le_bytes());
+    for field in [1, chunk_len as u16, sizes.len() as u16] {
+        file.extend(field.to_le_bytes());
+    }
+    sizes.iter().for_each(|s| file.extend(s.to_le_bytes()));
+    file.extend(body);
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    file.extend(crc.sum().to_le_bytes());
+    file.extend((data.len() as u32).to_le_bytes());
+    Ok(file)
+}
+
+/// Parse the `RA` subfield of the gzip extra field into the chunk length and the chunk sizes.
+fn parse_ra_field(mut extra: &[u8]) -> Option<(usize, Vec<usize>)> {
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if &extra[..2] == b"RA" && len >= 6 {
+            let field = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+            let (version, chunk_len, count) = (field(0), field(2), field(4));
+            if version != 1 || chunk_len == 0 || len < 6 + count * 2 {
+                return None;
+            }
+            let sizes = (0..count).map(|i| field(6 + i * 2)).collect();
+            return Some((chunk_len, sizes));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn read_u16(f: &mut File) -> std::io::Result<u16> {
+    let mut b = [0; 2];
+    f.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+/// Skip a zero-terminated string and return the number of bytes skipped.
+fn skip_zero_terminated(f: &mut File) -> std::io::Result<usize> {
+    let mut skipped = 0;
+    let mut b = [0; 1];
+    loop {
+        f.read_exact(&mut b)?;
+        skipped += 1;
+        if b[0] == 0 {
+            break Ok(skipped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, max_len, DictZip};
+    use flate2::read::GzDecoder;
+    use std::fs::write;
+    use std::io::Read;
+
+    #[test]
+    fn limit_chunk_count() {
+        let data = vec![0; max_len(16)];
+        assert!(compress(&data, 16).is_ok());
+        assert!(compress(&[&data[..], b"!"].concat(), 16).is_err());
+    }
+
+    #[test]
+    fn read_across_chunks() {
+        let data: Vec<u8> = (0..10000u32)
+            .flat_map(|i| i.to_string().into_bytes())
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("read-across-chunks.dz");
+        let dz = compress(&data, 1000).unwrap();
+        let mut inflated = Vec::new();
+        GzDecoder::new(dz.as_slice())
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, data);
+        write(&path, dz).unwrap();
+
+        let dz = DictZip::open(&path).unwrap().unwrap();
+        assert_eq!(dz.len(), data.len());
+        for (offset, size) in [
+            (0, 10),
+            (995, 10),
+            (1000, 1000),
+            (2500, 7000),
+            (0, data.len()),
+        ] {
+            assert_eq!(dz.read(offset, size).unwrap(), &data[offset..offset + size]);
+        }
+        assert!(dz.read(data.len() - 1, 2).is_err());
+    }
+}
@@ -0,0 +1,631 @@
+//! Look up words from ABBYY Lingvo DSL dictionaries.
+//!
+//! A DSL dictionary is a text file in UTF-16 or UTF-8, `.dsl` or compressed `.dsl.dz`. It
+//! starts with headers such as `#NAME "..."`, then has a card per entry: one or more
+//! headword lines, followed by the definition lines indented by spaces or tabs, with
+//! markup tags in square brackets such as `[m1]`, `[trn]`, `[ex]` or `[c]`. The headwords
+//! are indexed when the dictionary is first used, sorted ignoring the case of ASCII
+//! letters, and the index is cached like a stardict idx.
+use crate::fulltext::FullTextIndex;
+use crate::fuzzy::{BkTree, Ranking};
+use crate::pattern::Pattern;
+use crate::stardict::{Dict, Entry, EntryPart, Idx, IdxItem, Problem, Report};
+use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::cell::OnceCell;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The number of bytes read at the start of a DSL file to find its headers.
+const HEADER_LEN: usize = 16 * 1024;
+
+/// The most variants a headword with optional parts expands into.
+const MAX_VARIANTS: usize = 16;
+
+/// A DSL dictionary.
+pub struct Dsl {
+    file: PathBuf,
+    encoding: &'static Encoding,
+    /// The headers, such as `NAME` or `INDEX_LANGUAGE`, without their quotes.
+    headers: HashMap<String, String>,
+    /// The offset of the first line, after the byte order mark.
+    start: usize,
+    /// Each headword with the offset and the size of its card, sorted by [Idx::sort_folded],
+    /// built or loaded on first use.
+    idx: OnceCell<Idx>,
+    dict: Dict,
+    /// The problems tolerated while opening the dictionary, such as skipped DSL files.
+    warnings: Vec<String>,
+    fuzzy_index: OnceCell<BkTree>,
+    fulltext_index: OnceCell<FullTextIndex>,
+}
+
+/// Is the file a DSL dictionary, rather than the abbreviations of one?
+pub(crate) fn is_dsl_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = name
+        .strip_suffix(".dsl")
+        .or_else(|| name.strip_suffix(".dsl.dz"));
+    stem.is_some_and(|stem| !stem.ends_with("_abrv")) && path.is_file()
+}
+
+/// Find the DSL files in a directory, sorted.
+fn dsl_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_dsl_file(path))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Is the directory a DSL dictionary?
+pub fn is_dsl(dir: &Path) -> bool {
+    !dsl_files(dir).is_empty()
+}
+
+/// Detect the encoding from the byte order mark or the first character, with the length
+/// of the mark.
+fn detect_encoding(data: &[u8]) -> (&'static Encoding, usize) {
+    match data {
+        [0xff, 0xfe, ..] => (UTF_16LE, 2),
+        [0xfe, 0xff, ..] => (UTF_16BE, 2),
+        [0xef, 0xbb, 0xbf, ..] => (UTF_8, 3),
+        [b, 0, ..] if *b != 0 => (UTF_16LE, 0),
+        [0, b, ..] if *b != 0 => (UTF_16BE, 0),
+        _ => (UTF_8, 0),
+    }
+}
+
+/// Split the data into lines, with the offset of each in the data.
+fn lines<'a>(data: &'a [u8], encoding: &'static Encoding) -> Vec<(usize, &'a [u8])> {
+    let newline: &[u8] = match encoding {
+        e if e == UTF_16LE => b"\n\0",
+        e if e == UTF_16BE => b"\0\n",
+        _ => b"\n",
+    };
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, unit) in data.chunks_exact(newline.len()).enumerate() {
+        if unit == newline {
+            let end = i * newline.len();
+            lines.push((start, &data[start..end]));
+            start = end + newline.len();
+        }
+    }
+    if start < data.len() {
+        lines.push((start, &data[start..]));
+    }
+    lines
+}
+
+/// Decode a line, without its carriage return.
+fn decode(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    let mut text = encoding.decode_without_bom_handling(bytes).0.into_owned();
+    text.truncate(text.trim_end_matches('\r').len());
+    text
+}
+
+/// Remove the `{{comments}}`, which may span several lines, `in_comment` telling whether
+/// the text starts inside one.
+fn strip_comments(text: &str, in_comment: &mut bool) -> String {
+    let mut stripped = String::new();
+    let mut rest = text;
+    loop {
+        let delimiter = if *in_comment { "}}" } else { "{{" };
+        match rest.split_once(delimiter) {
+            Some((before, after)) => {
+                if !*in_comment {
+                    stripped.push_str(before);
+                }
+                *in_comment = !*in_comment;
+                rest = after;
+            }
+            None => {
+                if !*in_comment {
+                    stripped.push_str(rest);
+                }
+                return stripped;
+            }
+        }
+    }
+}
+
+/// Parse the `#NAME "value"` headers at the start of the lines.
+fn parse_headers(lines: &[String]) -> HashMap<String, String> {
+    lines
+        .iter()
+        .map(|line| line.trim_start_matches('\u{feff}'))
+        .take_while(|line| line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, value) = line[1..].split_once(char::is_whitespace)?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_ascii_uppercase(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Expand a headword line into the headwords it stands for: `colo(u)r` is both `colour`
+/// and `color`, the `{unsorted}` parts are left out and `\` escapes a character.
+fn headwords(line: &str) -> Vec<String> {
+    let mut variants = vec![String::new()];
+    let mut optional: Option<String> = None;
+    let mut unsorted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        let (c, escaped) = match c {
+            '\\' => match chars.next() {
+                Some(c) => (c, true),
+                None => break,
+            },
+            c => (c, false),
+        };
+        match c {
+            '{' if !escaped => unsorted = true,
+            '}' if !escaped => unsorted = false,
+            _ if unsorted => {}
+            '(' if !escaped && optional.is_none() => optional = Some(String::new()),
+            ')' if !escaped && optional.is_some() => {
+                let part = optional.take().unwrap();
+                if variants.len() * 2 <= MAX_VARIANTS {
+                    let with: Vec<_> = variants.iter().map(|v| format!("{}{}", v, part)).collect();
+                    variants.splice(0..0, with);
+                } else {
+                    variants.iter_mut().for_each(|v| v.push_str(&part));
+                }
+            }
+            c => match &mut optional {
+                Some(part) => part.push(c),
+                None => variants.iter_mut().for_each(|v| v.push(c)),
+            },
+        }
+    }
+
+    let mut seen = HashSet::new();
+    variants
+        .into_iter()
+        .map(|v| v.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|v| !v.is_empty() && seen.insert(v.clone()))
+        .collect()
+}
+
+/// Parse the cards of a DSL file into headwords, each with the offset and the size of
+/// its card, from its first headword line to its last definition line.
+///
+/// A card has one or more headword lines, then the definition lines starting with a
+/// space or a tab. The headers and the cards without definitions are skipped.
+fn parse_cards(data: &[u8], encoding: &'static Encoding, start: usize) -> Vec<IdxItem> {
+    let mut items = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    // The offset of the first headword line.
+    let mut card = 0;
+    // The offset of the end of the last definition line.
+    let mut body: Option<usize> = None;
+    let mut in_comment = false;
+
+    let mut finish = |words: &mut Vec<String>, card: usize, body: &mut Option<usize>| {
+        if let Some(end) = body.take() {
+            let mut seen = HashSet::new();
+            for word in words.drain(..) {
+                if seen.insert(word.clone()) {
+                    items.push((word, card, end - card));
+                }
+            }
+        }
+        words.clear();
+    };
+
+    let mut in_headers = true;
+    for (offset, bytes) in lines(&data[start..], encoding) {
+        let offset = start + offset;
+        let was_in_comment = in_comment;
+        let line = strip_comments(&decode(encoding, bytes), &mut in_comment);
+        if in_headers && line.starts_with('#') {
+            continue;
+        }
+        in_headers = false;
+        if line.starts_with([' ', '\t']) || was_in_comment {
+            if !words.is_empty() && !line.trim().is_empty() {
+                body = Some(offset + bytes.len());
+            }
+        } else if !line.trim().is_empty() {
+            if body.is_some() {
+                finish(&mut words, card, &mut body);
+            }
+            if words.is_empty() {
+                card = offset;
+            }
+            words.extend(headwords(line.trim()));
+        }
+    }
+    finish(&mut words, card, &mut body);
+    items
+}
+
+/// Split a card into its first headword and its definition lines.
+fn split_card(card: &str) -> (String, String) {
+    let mut in_comment = false;
+    let mut first = None;
+    let mut body = Vec::new();
+    for line in card.lines() {
+        let was_in_comment = in_comment;
+        let stripped = strip_comments(line, &mut in_comment);
+        if !body.is_empty() || stripped.starts_with([' ', '\t']) || was_in_comment {
+            body.push(line);
+        } else if first.is_none() {
+            first = headwords(stripped.trim()).into_iter().next();
+        }
+    }
+    (first.unwrap_or_default(), body.join("\n"))
+}
+
+/// Render the markup of the definition lines as plain text.
+///
+/// `[mN]` indents a line by N levels, `~` stands for the first headword of the card,
+/// `[s]` refers to a sound or a picture, `<<word>>` refers to another card and the other
+/// tags, such as `[trn]`, `[ex]` or `[c blue]`, are dropped keeping their text.
+fn render(body: &str, headword: &str) -> String {
+    let body = strip_comments(body, &mut false);
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let mut text = String::new();
+        let mut indent = 0;
+        let mut chars = line.trim().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => text.extend(chars.next()),
+                '~' => text.push_str(headword),
+                '[' => {
+                    let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                    match tag.split_whitespace().next().unwrap_or_default() {
+                        "m" => indent = 1,
+                        "s" => text.push('<'),
+                        "/s" => text.push('>'),
+                        name => {
+                            if let Some(n) = name.strip_prefix('m').and_then(|n| n.parse().ok()) {
+                                indent = n;
+                            }
+                        }
+                    }
+                }
+                '<' | '>' if chars.peek() == Some(&c) => {
+                    chars.next();
+                }
+                c => text.push(c),
+            }
+        }
+        lines.push(format!("{}{}", "  ".repeat(indent), text.trim_end()));
+    }
+    lines.join("\n").trim_end().to_owned()
+}
+
+impl Dsl {
+    /// Open the DSL dictionary in a directory, reading its headers only.
+    pub fn new(path: &Path) -> Result<Dsl> {
+        let mut files = dsl_files(path).into_iter();
+        let file = files
+            .next()
+            .with_context(|| format!("Missing .dsl or .dsl.dz file in {:?}", path))?;
+        // A dictionary is a directory, so the other DSL files need their own directories.
+        let warnings = files
+            .map(|other| {
+                format!(
+                    "Skipping {:?}, only {:?} is used in its directory",
+                    other, file
+                )
+            })
+            .collect();
+        let dict = Dict::new(file.clone())?;
+        let head = dict
+            .get(0, min(dict.len(), HEADER_LEN))
+            .with_context(|| format!("Failed to read DSL file {:?}", file))?;
+        let (encoding, bom) = detect_encoding(&head);
+        let head_lines: Vec<_> = lines(&head[bom..], encoding)
+            .into_iter()
+            .map(|(_, bytes)| decode(encoding, bytes))
+            .collect();
+        let headers = parse_headers(&head_lines);
+
+        Ok(Dsl {
+            file,
+            encoding,
+            headers,
+            start: bom,
+            idx: OnceCell::new(),
+            dict,
+            warnings,
+            fuzzy_index: OnceCell::new(),
+            fulltext_index: OnceCell::new(),
+        })
+    }
+
+    /// Get the headwords, indexing the cards unless the index is cached on first use.
+    fn idx(&self) -> Result<&Idx> {
+        if let Some(idx) = self.idx.get() {
+            return Ok(idx);
+        }
+        let idx = Idx::cached_folded(&self.file, || {
+            let data = self
+                .dict
+                .get(0, self.dict.len())
+                .with_context(|| format!("Failed to read DSL file {:?}", self.file))?;
+            Ok(parse_cards(&data, self.encoding, self.start))
+        })?;
+        Ok(self.idx.get_or_init(|| idx))
+    }
+
+    /// Build the entry of the `index`-th headword, with its definition as plain text.
+    fn entry(&self, index: usize) -> Result<Entry<'_>> {
+        let (word, offset, size) = self.idx()?.get(index);
+        let data = self
+            .dict
+            .get(offset, size)
+            .with_context(|| format!("Failed to read the definition of {:?}", word))?;
+        let card = self.encoding.decode_without_bom_handling(&data).0;
+        let (headword, body) = split_card(&card.replace('\r', ""));
+        let trans = render(&body, &headword);
+        Ok(Entry {
+            word,
+            parts: vec![EntryPart::Meaning(trans.clone())],
+            trans,
+            synonym: None,
+        })
+    }
+
+    /// Get a header, such as `NAME` or `INDEX_LANGUAGE`, empty if it is missing.
+    pub fn header(&self, name: &str) -> &str {
+        self.headers.get(name).map_or("", String::as_str)
+    }
+
+    /// Get the name of the dictionary, from the `#NAME` header or the DSL file.
+    pub fn dict_name(&self) -> &str {
+        match self.header("NAME") {
+            "" => {
+                let name = self.file.file_name().and_then(|name| name.to_str());
+                let name = name.unwrap_or_default();
+                name.strip_suffix(".dz")
+                    .unwrap_or(name)
+                    .trim_end_matches(".dsl")
+            }
+            name => name,
+        }
+    }
+
+    /// Get the directory of the dictionary.
+    pub fn path(&self) -> &Path {
+        self.file.parent().unwrap_or(&self.file)
+    }
+
+    /// Get the number of the headwords, the alternate ones included, 0 if the DSL file can't
+    /// be read.
+    pub fn wordcount(&self) -> usize {
+        self.idx().map_or(0, Idx::len)
+    }
+
+    /// Get the encoding of the DSL file.
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Get the problems tolerated while opening the dictionary.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Get the DSL file.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Look up all the entries of a word, ignoring the case of ASCII letters.
+    pub fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        self.idx()?
+            .folded_range(word)
+            .map(|i| self.entry(i))
+            .collect()
+    }
+
+    /// Find the headwords matching a glob or a regular expression, each once, sorted
+    /// ignoring the case of ASCII letters.
+    ///
+    /// Globs ignore the case of ASCII letters, regular expressions don't.
+    pub fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        let idx = self.idx()?;
+        let mut seen = HashSet::new();
+        Ok((0..idx.len())
+            .map(|i| idx.get(i).0)
+            .filter(|word| pattern.is_match(word) && seen.insert(*word))
+            .collect())
+    }
+
+    /// Look up a word with fuzzy searching enabled, ordering the entries by `ranking`.
+    pub fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        let idx = self.idx()?;
+        self.fuzzy_index()?
+            .search_ranked(word, ranking, history, |i| {
+                let (word, _, size) = idx.get(i);
+                Ok((word, size, i))
+            })?
+            .into_iter()
+            .map(|i| self.entry(i))
+            .collect()
+    }
+
+    /// Get the fuzzy searching index, loading it from the cache or building it on first use.
+    fn fuzzy_index(&self) -> Result<&BkTree> {
+        BkTree::cached(&self.fuzzy_index, &self.file, &[&self.file], || {
+            let idx = self.idx()?;
+            Ok((0..idx.len()).map(|i| (idx.get(i).0, i)))
+        })
+    }
+
+    /// Find the entries whose definition contains `phrase`, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// Returns at most [FULLTEXT_LIMIT](crate::stardict::FULLTEXT_LIMIT) entries in the order
+    /// of their headwords.
+    pub fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        self.fulltext_index()?
+            .search(phrase, |doc| self.entry(doc).map(Some))
+    }
+
+    /// Get the full-text index, loading it from the cache or building it on first use.
+    fn fulltext_index(&self) -> Result<&FullTextIndex> {
+        let file = &self.file;
+        FullTextIndex::cached(
+            &self.fulltext_index,
+            self.dict_name(),
+            file,
+            &[file],
+            || {
+                let docs = (0..self.idx()?.len())
+                    .filter_map(|i| Some((i as u32, self.entry(i).ok()?.trans)));
+                Ok(docs)
+            },
+        )
+    }
+
+    /// Check that the DSL file can be read and its cards indexed.
+    ///
+    /// See [Dsl::verify] for a full check.
+    pub fn check(&self) -> Result<()> {
+        self.idx()?;
+        Ok(())
+    }
+
+    /// Check that the cards can be indexed and that every card lies in the DSL file.
+    pub fn verify(&self) -> Report {
+        let mut problems = Vec::new();
+        match self.idx() {
+            Ok(idx) => verify_idx(idx, self.dict.len(), &mut problems),
+            Err(e) => problems.push(Problem::new("index", format!("{:#}", e))),
+        }
+        Report {
+            path: self.path().to_owned(),
+            bookname: Some(self.dict_name().to_owned()),
+            problems,
+        }
+    }
+}
+
+/// Check that every card lies in the DSL file of `len` bytes.
+fn verify_idx(idx: &Idx, len: usize, problems: &mut Vec<Problem>) {
+    let items: Vec<_> = (0..idx.len())
+        .filter(|&i| {
+            let (_, offset, size) = idx.get(i);
+            offset.checked_add(size).is_none_or(|end| end > len)
+        })
+        .collect();
+    if let Some(&first) = items.first() {
+        problems.push(Problem::with_items(
+            "out_of_range",
+            format!(
+                "{} cards are out of the DSL file, the first is {:?}",
+                items.len(),
+                idx.get(first).0
+            ),
+            items,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{headwords, is_dsl, Dsl};
+    use crate::cache::test::tempdir;
+    use crate::fuzzy::Ranking;
+    use crate::pattern::Pattern;
+    use flate2::{write::GzEncoder, Compression};
+    use std::collections::HashSet;
+    use std::fs::{remove_file, write};
+    use std::io::Write;
+
+    const DSL: &str = "#NAME \"Test DSL\"
+#INDEX_LANGUAGE \"English\"
+#CONTENTS_LANGUAGE \"Russian\"
+
+colo(u)r
+hue{{a comment}}
+\t[m1][p]n[/p] [trn]цвет[/trn][/m]
+\t[m2][ex][c gray]the ~ of the sky[/c][/ex][/m]
+{{a card
+for nothing}}
+rust\\(y\\)
+ [m1][trn]ржавчина, см. <<iron>>[/trn] [s]rust.wav[/s]
+
+iron
+\t[m1]железо
+";
+
+    #[test]
+    fn read_dsl() {
+        assert_eq!(headwords("colo(u)r"), ["colour", "color"]);
+        assert_eq!(headwords("{to }go"), ["go"]);
+        assert_eq!(headwords("a (b) c"), ["a b c", "a c"]);
+        assert_eq!(headwords("\\{x\\}"), ["{x}"]);
+
+        let dir = tempdir();
+        let dir = dir.path();
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(DSL.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        write(dir.join("test.dsl"), utf16).unwrap();
+        write(dir.join("test_abrv.dsl"), "#NAME \"Abbreviations\"\n").unwrap();
+
+        assert!(is_dsl(dir));
+        let d = Dsl::new(dir).unwrap();
+        assert!(d.idx.get().is_none());
+        assert!(d.check().is_ok());
+        assert_eq!(d.dict_name(), "Test DSL");
+        assert_eq!(d.header("CONTENTS_LANGUAGE"), "Russian");
+        assert_eq!(d.encoding(), "UTF-16LE");
+        assert_eq!(d.wordcount(), 5);
+        assert!(d.verify().problems.is_empty());
+
+        let entries = d.exact_lookup_all("Color").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].trans, "  n цвет\n    the colour of the sky");
+        assert_eq!(
+            d.exact_lookup_all("hue").unwrap()[0].trans,
+            "  n цвет\n    the colour of the sky"
+        );
+        assert_eq!(
+            d.exact_lookup_all("rust(y)").unwrap()[0].trans,
+            "  ржавчина, см. iron <rust.wav>"
+        );
+
+        let pattern = Pattern::parse("colo*").unwrap().unwrap();
+        assert_eq!(d.pattern_search(&pattern).unwrap(), ["color", "colour"]);
+        let entries = d
+            .fuzzy_lookup_ranked("irn", &Ranking::TYPO, &HashSet::new())
+            .unwrap();
+        assert_eq!(entries[0].word, "iron");
+
+        // Compressed and in UTF-8 without a byte order mark.
+        remove_file(dir.join("test.dsl")).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(DSL.as_bytes()).unwrap();
+        write(dir.join("test.dsl.dz"), gz.finish().unwrap()).unwrap();
+        let d = Dsl::new(dir).unwrap();
+        assert_eq!(d.encoding(), "UTF-8");
+        assert_eq!(d.exact_lookup_all("iron").unwrap()[0].trans, "  железо");
+        assert!(d.warnings().is_empty());
+
+        write(dir.join("z.dsl"), DSL).unwrap();
+        let d = Dsl::new(dir).unwrap();
+        assert!(d.warnings()[0].contains("z.dsl"));
+    }
+}
@@ -0,0 +1,263 @@
+//! Full-text searching inside the definitions with an inverted index.
+//!
+//! Latin text is split into lower-case words. CJK text has no spaces, so every character
+//! and every pair of adjacent characters is indexed, and a phrase is found through its
+//! pairs. The index only narrows down the candidates, which must still contain the phrase.
+//!
+//! Latin words are indexed whole, so a phrase only matches whole words: `rust` finds
+//! `rust-coloured` but not `rusty`.
+//!
+//! The index is cached on disk next to the dictionary, or in the cache directory if the
+//! dictionary directory is read-only, and is rebuilt when the dictionary files change.
+use crate::cache::{cache_path, file_stamp, write_cache, Reader};
+use crate::stardict::{Entry, FULLTEXT_LIMIT};
+use anyhow::Result;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"DIOXFTS1";
+
+/// An inverted index from tokens to the documents containing them.
+pub struct FullTextIndex {
+    /// The modification time and the size of the indexed files.
+    stamp: Vec<u64>,
+    /// The sorted documents of each token.
+    postings: HashMap<String, Vec<u32>>,
+}
+
+impl FullTextIndex {
+    /// Create an empty index of the files with the given [crate::cache::file_stamp].
+    pub fn new(stamp: Vec<u64>) -> FullTextIndex {
+        FullTextIndex {
+            stamp,
+            postings: HashMap::new(),
+        }
+    }
+
+    /// Get the index in `cell`, on first use loading the index of the dictionary named
+    /// `name` whose main file is `file` from the cache, or building it from the documents
+    /// and their text and caching it.
+    ///
+    /// The cache is stale once one of `files`, the dictionary files, changes.
+    pub fn cached<'c, I>(
+        cell: &'c OnceCell<FullTextIndex>,
+        name: &str,
+        file: &Path,
+        files: &[&Path],
+        docs: impl FnOnce() -> Result<I>,
+    ) -> Result<&'c FullTextIndex>
+    where
+        I: IntoIterator<Item = (u32, String)>,
+    {
+        if let Some(index) = cell.get() {
+            return Ok(index);
+        }
+        let stamp = file_stamp(files)?;
+        let paths = cache_paths(file);
+        let index = match paths.iter().find_map(|p| FullTextIndex::load(p, &stamp)) {
+            Some(index) => index,
+            None => {
+                eprintln!("Building the full-text index of {}", name);
+                let mut index = FullTextIndex::new(stamp);
+                for (doc, text) in docs()? {
+                    index.add(doc, &text);
+                }
+                if !paths.iter().any(|p| index.save(p).is_ok()) {
+                    eprintln!("Failed to cache the full-text index of {}", name);
+                }
+                index
+            }
+        };
+        Ok(cell.get_or_init(|| index))
+    }
+
+    /// Find the entries whose definition contains `phrase`, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// `entry` gets the entry of a document, or `None` to leave it out. Returns at most
+    /// [FULLTEXT_LIMIT] entries in the order of the documents.
+    pub fn search<'a>(
+        &self,
+        phrase: &str,
+        mut entry: impl FnMut(usize) -> Result<Option<Entry<'a>>>,
+    ) -> Result<Vec<Entry<'a>>> {
+        let phrase = phrase.to_ascii_lowercase();
+        let mut entries = Vec::new();
+        for doc in self.candidates(&phrase) {
+            let Some(entry) = entry(doc as usize)? else {
+                continue;
+            };
+            if entry.trans.to_ascii_lowercase().contains(&phrase) {
+                entries.push(entry);
+                if entries.len() == FULLTEXT_LIMIT {
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Add a document, documents must be added in ascending order.
+    pub fn add(&mut self, doc: u32, text: &str) {
+        for token in tokens(text, true) {
+            let docs = self.postings.entry(token).or_default();
+            if docs.last() != Some(&doc) {
+                docs.push(doc);
+            }
+        }
+    }
+
+    /// Find the documents containing all the tokens of the query, in ascending order.
+    pub fn candidates(&self, query: &str) -> Vec<u32> {
+        let tokens = tokens(query, false);
+        let mut lists = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match self.postings.get(token) {
+                Some(docs) => lists.push(docs.as_slice()),
+                None => return vec![],
+            }
+        }
+        // Intersect starting from the rarest token.
+        lists.sort_by_key(|docs| docs.len());
+        let Some((first, rest)) = lists.split_first() else {
+            return vec![];
+        };
+        first
+            .iter()
+            .copied()
+            .filter(|doc| rest.iter().all(|docs| docs.binary_search(doc).is_ok()))
+            .collect()
+    }
+
+    /// Load a cached index, unless it is missing, broken or stale.
+    pub fn load(path: &Path, stamp: &[u64]) -> Option<FullTextIndex> {
+        let data = read(path).ok()?;
+        let mut reader = Reader(data.strip_prefix(MAGIC)?);
+        let len = reader.u32()? as usize;
+        if (0..len).map(|_| reader.u64()).collect::<Option<Vec<_>>>()? != stamp {
+            return None;
+        }
+        let mut postings = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let token = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+            let count = reader.u32()? as usize;
+            let docs = (0..count).map(|_| reader.u32()).collect::<Option<_>>()?;
+            postings.insert(token, docs);
+        }
+        Some(FullTextIndex {
+            stamp: stamp.to_vec(),
+            postings,
+        })
+    }
+
+    /// Save the index to a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut data = MAGIC.to_vec();
+        data.extend((self.stamp.len() as u32).to_le_bytes());
+        self.stamp.iter().for_each(|n| data.extend(n.to_le_bytes()));
+        data.extend((self.postings.len() as u32).to_le_bytes());
+        for (token, docs) in &self.postings {
+            data.extend((token.len() as u32).to_le_bytes());
+            data.extend(token.as_bytes());
+            data.extend((docs.len() as u32).to_le_bytes());
+            docs.iter().for_each(|doc| data.extend(doc.to_le_bytes()));
+        }
+        write_cache(path, &data)
+    }
+}
+
+/// Get the paths where the index of the dictionary with the given `.ifo` file is cached:
+/// next to the dictionary first, then in the cache directory.
+fn cache_paths(ifo: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![ifo.with_extension("fts")];
+    paths.extend(cache_path(ifo, "fts"));
+    paths
+}
+
+/// Is the character written without spaces between words?
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}' // Hangul
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2fa1f}')
+}
+
+/// Split text into tokens: lower-case Latin words and CJK characters and bigrams.
+///
+/// The single characters of longer CJK runs are only needed for indexing, as a phrase
+/// is found through its bigrams.
+pub fn tokens(text: &str, all_unigrams: bool) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut run: Vec<char> = Vec::new();
+    let mut flush = |word: &mut String, run: &mut Vec<char>| {
+        if !word.is_empty() {
+            tokens.push(std::mem::take(word));
+        }
+        if run.len() == 1 || all_unigrams {
+            tokens.extend(run.iter().map(|c| c.to_string()));
+        }
+        tokens.extend(run.windows(2).map(|w| w.iter().collect()));
+        run.clear();
+    };
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                flush(&mut word, &mut vec![]);
+            }
+            run.push(c);
+        } else if c.is_alphanumeric() {
+            if !run.is_empty() {
+                flush(&mut String::new(), &mut run);
+            }
+            word.extend(c.to_lowercase());
+        } else {
+            flush(&mut word, &mut run);
+        }
+    }
+    flush(&mut word, &mut run);
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tokens, FullTextIndex};
+
+    #[test]
+    fn tokenize_mixed_text() {
+        assert_eq!(
+            tokens("n. 铁锈; Rust-colour", false),
+            ["n", "铁锈", "rust", "colour"]
+        );
+        assert_eq!(tokens("锈，铁锈色", false), ["锈", "铁锈", "锈色"]);
+        assert_eq!(tokens("铁锈色", true), ["铁", "锈", "色", "铁锈", "锈色"]);
+    }
+
+    #[test]
+    fn save_and_load_index() {
+        let mut index = FullTextIndex::new(vec![1, 2]);
+        index.add(0, "n. 铁锈");
+        index.add(1, "adj. 生锈的");
+        index.add(2, "铁的; iron");
+        assert_eq!(index.candidates("铁锈"), [0]);
+        assert_eq!(index.candidates("锈"), [0, 1]);
+        assert_eq!(index.candidates("IRON"), [2]);
+        assert!(index.candidates("iro").is_empty());
+        assert!(index.candidates("铜").is_empty());
+        assert!(index.candidates("...").is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.fts");
+        index.save(&path).unwrap();
+        assert!(FullTextIndex::load(&path, &[1, 3]).is_none());
+        let loaded = FullTextIndex::load(&path, &[1, 2]).unwrap();
+        assert_eq!(loaded.postings, index.postings);
+    }
+}
@@ -0,0 +1,482 @@
+//! Fuzzy searching with a [BK-tree](https://en.wikipedia.org/wiki/BK-tree).
+//!
+//! The tree is built once over all the words of a dictionary, after that a search only
+//! visits the subtrees that may contain words within the maximum edit distance.
+//! The words found can then be ordered by a [Ranking].
+//!
+//! Building the tree takes many distance calculations, so it is cached on disk and
+//! rebuilt only when the dictionary files change.
+use crate::cache::{cache_path, file_stamp, write_cache, Reader};
+use crate::stardict::{FUZZY_LIMIT, FUZZY_MAX_DISTANCE};
+use anyhow::Result;
+use std::cell::OnceCell;
+use std::cmp::min;
+use std::collections::HashSet;
+use std::fs::read;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"DIOXBKT1";
+
+/// A BK-tree over words under the Levenshtein distance.
+pub struct BkTree {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    word: Vec<char>,
+    /// The values of all the words equal to this one.
+    values: Vec<usize>,
+    /// The children with their distance to this node.
+    children: Vec<(usize, usize)>,
+}
+
+impl BkTree {
+    /// Build a tree from words and their values.
+    pub fn new<'a>(words: impl IntoIterator<Item = (&'a str, usize)>) -> BkTree {
+        let mut tree = BkTree { nodes: Vec::new() };
+        let mut row = Vec::new();
+        for (word, value) in words {
+            tree.insert(normalize(word), value, &mut row);
+        }
+        tree
+    }
+
+    /// Get the tree in `cell`, on first use loading the tree of the dictionary whose main
+    /// file is `file` from the cache, or building it from the words and caching it.
+    ///
+    /// The cache is stale once one of `files`, the dictionary files, changes.
+    pub fn cached<'c, 'a, I>(
+        cell: &'c OnceCell<BkTree>,
+        file: &Path,
+        files: &[&Path],
+        words: impl FnOnce() -> Result<I>,
+    ) -> Result<&'c BkTree>
+    where
+        I: IntoIterator<Item = (&'a str, usize)>,
+    {
+        if let Some(tree) = cell.get() {
+            return Ok(tree);
+        }
+        let stamp = file_stamp(files)?;
+        let path = cache_path(file, "bkt");
+        let tree = match path.as_deref().and_then(|p| BkTree::load(p, &stamp)) {
+            Some(tree) => tree,
+            None => {
+                let tree = BkTree::new(words()?);
+                if let Some(path) = path {
+                    // The cache only makes the next search faster, so a failure to write
+                    // is ignored.
+                    let _ = tree.save(&path, &stamp);
+                }
+                tree
+            }
+        };
+        Ok(cell.get_or_init(|| tree))
+    }
+
+    /// Load a cached tree, unless it is missing, broken or stale.
+    pub fn load(path: &Path, stamp: &[u64]) -> Option<BkTree> {
+        let data = read(path).ok()?;
+        let mut reader = Reader(data.strip_prefix(MAGIC)?);
+        let len = reader.u32()? as usize;
+        if (0..len).map(|_| reader.u64()).collect::<Option<Vec<_>>>()? != stamp {
+            return None;
+        }
+        let count = reader.u32()? as usize;
+        let mut nodes = Vec::with_capacity(min(count, data.len()));
+        for _ in 0..count {
+            let len = reader.u32()? as usize;
+            let word = std::str::from_utf8(reader.take(len)?)
+                .ok()?
+                .chars()
+                .collect();
+            let values = (0..reader.u32()?)
+                .map(|_| reader.u64().map(|v| v as usize))
+                .collect::<Option<_>>()?;
+            let children = (0..reader.u32()?)
+                .map(|_| Some((reader.u32()? as usize, reader.u32()? as usize)))
+                .collect::<Option<Vec<_>>>()?;
+            if children.iter().any(|&(_, child)| child >= count) {
+                return None;
+            }
+            nodes.push(Node {
+                word,
+                values,
+                children,
+            });
+        }
+        Some(BkTree { nodes })
+    }
+
+    /// Save the tree to a file, with the stamp of the files it was built from.
+    pub fn save(&self, path: &Path, stamp: &[u64]) -> Result<()> {
+        let mut data = MAGIC.to_vec();
+        data.extend((stamp.len() as u32).to_le_bytes());
+        stamp.iter().for_each(|n| data.extend(n.to_le_bytes()));
+        data.extend((self.nodes.len() as u32).to_le_bytes());
+        for node in &self.nodes {
+            let word: String = node.word.iter().collect();
+            data.extend((word.len() as u32).to_le_bytes());
+            data.extend(word.as_bytes());
+            data.extend((node.values.len() as u32).to_le_bytes());
+            node.values
+                .iter()
+                .for_each(|&v| data.extend((v as u64).to_le_bytes()));
+            data.extend((node.children.len() as u32).to_le_bytes());
+            for &(dist, child) in &node.children {
+                data.extend((dist as u32).to_le_bytes());
+                data.extend((child as u32).to_le_bytes());
+            }
+        }
+        write_cache(path, &data)
+    }
+
+    fn insert(&mut self, word: Vec<char>, value: usize, row: &mut Vec<usize>) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                word,
+                values: vec![value],
+                children: Vec::new(),
+            });
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let dist = levenshtein(&self.nodes[current].word, &word, row);
+            if dist == 0 {
+                self.nodes[current].values.push(value);
+                return;
+            }
+            match self.nodes[current]
+                .children
+                .iter()
+                .find(|(d, _)| *d == dist)
+            {
+                Some(&(_, child)) => current = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node {
+                        word,
+                        values: vec![value],
+                        children: Vec::new(),
+                    });
+                    self.nodes[current].children.push((dist, child));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Find the values of the words within `max_distance` of `word`.
+    ///
+    /// Returns at most `limit` pairs of distance and value, the nearest first.
+    pub fn search(&self, word: &str, max_distance: usize, limit: usize) -> Vec<(usize, usize)> {
+        let word = normalize(word);
+        let mut row = Vec::new();
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let dist = levenshtein(&node.word, &word, &mut row);
+            if dist <= max_distance {
+                found.extend(node.values.iter().map(|&value| (dist, value)));
+            }
+            // By the triangle inequality, only the children whose distance to this node
+            // is close to `dist` may be within reach.
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| d.abs_diff(dist) <= max_distance)
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        found.sort_unstable();
+        found.truncate(limit);
+        found
+    }
+
+    /// Find the words near `word` and keep the best [FUZZY_LIMIT] of them by `ranking`.
+    ///
+    /// `candidate` gets the word, the size of its entry and a value to return for the value
+    /// of a word found. A transposition counts as two edits in the tree, so the search goes
+    /// one edit farther than [FUZZY_MAX_DISTANCE], and every word found is ranked.
+    pub fn search_ranked<'a, T>(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+        mut candidate: impl FnMut(usize) -> Result<(&'a str, usize, T)>,
+    ) -> Result<Vec<T>> {
+        let candidates = self
+            .search(word, FUZZY_MAX_DISTANCE + 1, usize::MAX)
+            .into_iter()
+            .map(|(_, value)| candidate(value))
+            .collect::<Result<_>>()?;
+        Ok(ranking.rank(word, candidates, history, FUZZY_LIMIT))
+    }
+}
+
+/// Weights of the signals used to rank fuzzy suggestions, lower scores first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ranking {
+    /// The weight of the edit distance.
+    pub edit: f64,
+    /// The cost of transposing two adjacent characters, relative to the other edits.
+    pub transposition: f64,
+    /// The cost of substituting a key by its neighbour on a QWERTY keyboard,
+    /// relative to the other edits.
+    pub neighbour_key: f64,
+    /// The bonus for each leading character shared with the query.
+    pub prefix: f64,
+    /// The bonus for a long entry, given between 0 and 1 by [Ranking::rank]. Dictionaries
+    /// don't record how common their words are, but common words tend to have the longest
+    /// entries.
+    pub entry_size: f64,
+    /// The bonus for a word already in the history.
+    pub history: f64,
+}
+
+impl Ranking {
+    /// Rank by the edit distance only.
+    pub const DISTANCE: Ranking = Ranking {
+        edit: 1.0,
+        transposition: 1.0,
+        neighbour_key: 1.0,
+        prefix: 0.0,
+        entry_size: 0.0,
+        history: 0.0,
+    };
+
+    /// Rank typing mistakes first: transpositions, neighbouring keys and a wrong ending.
+    pub const TYPO: Ranking = Ranking {
+        edit: 1.0,
+        transposition: 0.5,
+        neighbour_key: 0.5,
+        prefix: 0.1,
+        entry_size: 0.0,
+        history: 0.0,
+    };
+
+    /// Rank by all the signals.
+    pub const BALANCED: Ranking = Ranking {
+        edit: 1.0,
+        transposition: 0.6,
+        neighbour_key: 0.6,
+        prefix: 0.1,
+        entry_size: 0.3,
+        history: 0.5,
+    };
+
+    /// Rank words already looked up first.
+    pub const HISTORY: Ranking = Ranking {
+        edit: 1.0,
+        transposition: 0.6,
+        neighbour_key: 0.6,
+        prefix: 0.1,
+        entry_size: 0.3,
+        history: 1.5,
+    };
+
+    /// Score a suggestion for the query, the lower the better.
+    pub fn score(&self, query: &str, word: &str, entry_size: f64, in_history: bool) -> f64 {
+        let query = normalize(query);
+        let word = normalize(word);
+        let prefix = query.iter().zip(&word).take_while(|(a, b)| a == b).count();
+        self.edit * typo_distance(&query, &word, self.transposition, self.neighbour_key)
+            - self.prefix * prefix as f64
+            - self.entry_size * entry_size
+            - if in_history { self.history } else { 0.0 }
+    }
+
+    /// Order the candidates for the query, the best first, and keep `limit` of them.
+    ///
+    /// Each candidate is a word, the size of its entry and a value to return. The size is
+    /// scaled logarithmically against the largest candidate for [Ranking::entry_size].
+    /// `history` holds the words already looked up.
+    pub fn rank<T>(
+        &self,
+        query: &str,
+        candidates: Vec<(&str, usize, T)>,
+        history: &HashSet<String>,
+        limit: usize,
+    ) -> Vec<T> {
+        let max_size = candidates
+            .iter()
+            .map(|(_, size, _)| *size)
+            .max()
+            .unwrap_or_default();
+        let mut scored: Vec<_> = candidates
+            .into_iter()
+            .map(|(word, size, value)| {
+                let entry_size = (size as f64).ln_1p() / (max_size as f64).ln_1p().max(1.0);
+                let score = self.score(query, word, entry_size, history.contains(word));
+                (score, word, value)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(b.1)));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, value)| value)
+            .collect()
+    }
+}
+
+impl Default for Ranking {
+    fn default() -> Ranking {
+        Ranking::BALANCED
+    }
+}
+
+/// Calculate the optimal string alignment distance, where transposing two adjacent
+/// characters costs `transposition` and substituting a neighbouring key costs `neighbour_key`.
+pub fn typo_distance(a: &[char], b: &[char], transposition: f64, neighbour_key: f64) -> f64 {
+    let mut dist = vec![vec![0.0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i as f64;
+    }
+    for (j, d) in dist[0].iter_mut().enumerate() {
+        *d = j as f64;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = if a[i - 1] == b[j - 1] {
+                0.0
+            } else if is_neighbour_key(a[i - 1], b[j - 1]) {
+                neighbour_key
+            } else {
+                1.0
+            };
+            let mut d = (dist[i - 1][j - 1] + substitution)
+                .min(dist[i - 1][j] + 1.0)
+                .min(dist[i][j - 1] + 1.0);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d = d.min(dist[i - 2][j - 2] + transposition);
+            }
+            dist[i][j] = d;
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+/// Are the two letters next to each other on a QWERTY keyboard?
+fn is_neighbour_key(a: char, b: char) -> bool {
+    const ROWS: [(&str, f64); 3] = [("qwertyuiop", 0.0), ("asdfghjkl", 0.25), ("zxcvbnm", 0.75)];
+    let position = |c: char| {
+        ROWS.iter().enumerate().find_map(|(row, (keys, shift))| {
+            keys.find(c).map(|col| (row as f64, col as f64 + shift))
+        })
+    };
+    match (position(a), position(b)) {
+        (Some((row_a, col_a)), Some((row_b, col_b))) => {
+            let (rows, cols) = ((row_a - row_b).abs(), (col_a - col_b).abs());
+            (rows == 0.0 && cols == 1.0) || (rows == 1.0 && cols < 1.0)
+        }
+        _ => false,
+    }
+}
+
+fn normalize(word: &str) -> Vec<char> {
+    word.to_lowercase().chars().collect()
+}
+
+/// Calculate the Levenshtein distance, using `row` as the working memory.
+fn levenshtein(a: &[char], b: &[char], row: &mut Vec<usize>) -> usize {
+    row.clear();
+    row.extend(0..=b.len());
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == cb {
+                diagonal
+            } else {
+                min(min(row[j], above), diagonal) + 1
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{levenshtein, normalize, BkTree, Ranking};
+    use std::collections::HashSet;
+
+    #[test]
+    fn search_like_full_scan() {
+        let words = [
+            "rust", "rest", "roast", "crate", "create", "cargo", "car", "cart", "Trust", "rusty",
+            "dust", "rust",
+        ];
+        let tree = BkTree::new(words.iter().enumerate().map(|(i, w)| (*w, i)));
+        for query in ["rst", "crade", "cago", "ruts", "xyz"] {
+            for max_distance in 0..3 {
+                let mut expected: Vec<_> = words
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| {
+                        let d = levenshtein(&normalize(w), &normalize(query), &mut vec![]);
+                        (d, i)
+                    })
+                    .filter(|(d, _)| *d <= max_distance)
+                    .collect();
+                expected.sort_unstable();
+                assert_eq!(tree.search(query, max_distance, usize::MAX), expected);
+            }
+        }
+        assert_eq!(tree.search("rust", 1, 2), [(0, 0), (0, 11)]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkt");
+        tree.save(&path, &[1, 2]).unwrap();
+        assert!(BkTree::load(&path, &[1, 3]).is_none());
+        let loaded = BkTree::load(&path, &[1, 2]).unwrap();
+        for query in ["rst", "crade", "ruts"] {
+            assert_eq!(
+                loaded.search(query, 2, usize::MAX),
+                tree.search(query, 2, usize::MAX)
+            );
+        }
+    }
+
+    #[test]
+    fn rank_typos() {
+        let rank = |ranking: Ranking, query: &str, words: &[&str], history: &[&str]| {
+            let mut words = words.to_vec();
+            words.sort_by(|a, b| {
+                let score = |w: &str| ranking.score(query, w, 0.0, history.contains(&w));
+                score(a).total_cmp(&score(b))
+            });
+            words[0].to_string()
+        };
+        // A transposition is cheaper than another edit.
+        assert_eq!(rank(Ranking::TYPO, "teh", &["tea", "the"], &[]), "the");
+        // `t` is next to `r` but not to `k`.
+        assert_eq!(rank(Ranking::TYPO, "rusr", &["rusk", "rust"], &[]), "rust");
+        // Without the keyboard, both are one substitution away and tie on the word.
+        assert_eq!(
+            Ranking::DISTANCE.score("rusr", "rust", 0.0, false),
+            Ranking::DISTANCE.score("rusr", "rusk", 0.0, false)
+        );
+        let candidates = vec![("rust", 1, "rust"), ("rusk", 1, "rusk")];
+        assert_eq!(
+            Ranking::DISTANCE.rank("rusr", candidates, &HashSet::new(), 2),
+            ["rusk", "rust"]
+        );
+        assert_eq!(
+            rank(Ranking::HISTORY, "cargo", &["cargo", "cargos"], &["cargos"]),
+            "cargos"
+        );
+    }
+}
@@ -4,18 +4,19 @@ use chrono::Utc;
 use dirs::cache_dir;
 use prettytable::{Attr, Cell, Row, Table};
 use rusqlite::Connection;
-use std::fs::create_dir;
+use std::collections::HashSet;
+use std::fs::create_dir_all;
 use std::path::PathBuf;
 
 /// Allowed diffculty level types of a word.
 pub static ALLOWED_TYPES: [&str; 7] = ["CET4", "CET6", "TOEFL", "IELTS", "GMAT", "GRE", "SAT"];
 
 /// Check and generate cache directory path.
-fn check_cache() -> Result<PathBuf> {
+pub(crate) fn check_cache() -> Result<PathBuf> {
     let mut path = cache_dir().with_context(|| "Couldn't find cache directory")?;
     path.push("dioxionary");
     if !path.exists() {
-        create_dir(&path).with_context(|| format!("Failed to create directory {:?}", path))?;
+        create_dir_all(&path).with_context(|| format!("Failed to create directory {:?}", path))?;
     }
     path.push("dioxionary.db");
     Ok(path)
@@ -107,6 +108,21 @@ pub fn list_history(type_: Option<String>, sort: bool, table: bool, column: usiz
     Ok(())
 }
 
+/// Get all the words in the history.
+pub fn history_words() -> Result<HashSet<String>> {
+    let path = check_cache()?;
+
+    let conn = Connection::open(path)?;
+
+    // Nothing has been looked up yet.
+    let Ok(mut stmt) = conn.prepare("SELECT WORD FROM HISTORY") else {
+        return Ok(HashSet::new());
+    };
+    let word_iter = stmt.query_map([], |row| row.get(0) as rusqlite::Result<String>)?;
+
+    Ok(word_iter.filter_map(|x| x.ok()).collect())
+}
+
 /// Count the history.
 pub fn count_history() -> Result<()> {
     let path = check_cache()?;
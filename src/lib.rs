@@ -1,16 +1,46 @@
 //! StarDict in Rust!
 //! Use offline or online dictionary to look up words and memorize words in the terminal!
+pub mod build;
+pub mod cache;
 pub mod cli;
+pub mod collate;
+pub mod config;
+pub mod datadir;
 pub mod dict;
+pub mod dictd;
+pub mod dictionary;
+pub mod dictzip;
+pub mod dsl;
+pub mod fulltext;
+pub mod fuzzy;
 pub mod history;
+pub mod lzo;
+pub mod mdict;
+pub mod pattern;
+pub mod resource;
 pub mod stardict;
-use std::fs::DirEntry;
+pub mod treedict;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use anyhow::{anyhow, Context, Result};
-use dialoguer::{console::Term, theme::ColorfulTheme, Select};
+use cli::{Scope, SourceFormat};
+use config::DictConfig;
+use datadir::DataDirs;
+use dialoguer::{
+    console::{style, Term},
+    theme::ColorfulTheme,
+    Select,
+};
+use dictionary::Dictionary;
+use fuzzy::Ranking;
+use pattern::Pattern;
 use prettytable::{Attr, Cell, Row, Table};
+use rusqlite::Connection;
 use rustyline::error::ReadlineError;
-use stardict::StarDict;
+use stardict::{Entry, Problem, Report, StarDictBuilder};
+use treedict::{Node, TreeDict};
 
 /// Lookup word from the Internel and add the result to history.
 fn lookup_online(word: &str) -> Result<()> {
@@ -22,74 +52,197 @@ fn lookup_online(word: &str) -> Result<()> {
     Ok(())
 }
 
-/// Get the entries of the stardicts.
-fn get_dicts_entries() -> Result<Vec<DirEntry>> {
-    let mut dioxionary_dir = dirs::config_dir();
-    let dioxionary_dir = dioxionary_dir
-        .as_mut()
-        .map(|dir| {
-            dir.push("dioxionary");
-            dir
-        })
-        .filter(|dir| dir.is_dir());
-
-    let mut stardict_compatible_dir = dirs::home_dir();
-    let stardict_compatible_dir = stardict_compatible_dir
-        .as_mut()
-        .map(|dir| {
-            dir.push(".stardict");
-            dir.push("dic");
-            dir
-        })
-        .filter(|dir| dir.is_dir());
+/// Warn that the stardict at `path` is broken and skipped, only once per stardict.
+fn warn_broken(path: &Path, err: &anyhow::Error) {
+    static WARNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if !warned.iter().any(|p| p == path) {
+        warned.push(path.to_owned());
+        eprintln!("Skipping broken dictionary {:?}: {:#}", path, err);
+    }
+}
 
-    let path = match (&dioxionary_dir, &stardict_compatible_dir) {
-        (Some(dir), _) => dir,
-        (None, Some(dir)) => dir,
-        (None, None) => return Err(anyhow!("Couldn't find configuration directory")),
-    };
+/// Print the problems tolerated while opening a dictionary.
+fn print_warnings(d: &dyn Dictionary) {
+    for warning in d.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+}
 
-    let mut dicts: Vec<_> = path
-        .read_dir()
-        .with_context(|| format!("Failed to open configuration directory {:?}", path))?
-        .filter_map(|x| x.ok())
-        .collect();
+/// Get the result of using a dictionary, or warn and get nothing if it is broken.
+fn skip_broken<T>(d: &dyn Dictionary, result: Result<T>) -> Option<T> {
+    result.map_err(|e| warn_broken(d.path(), &e)).ok()
+}
 
-    dicts.sort_by_key(|a| a.file_name());
+/// Open the configuration database of the data directories.
+fn open_config(data_dirs: &DataDirs) -> Result<Connection> {
+    match &data_dirs.config {
+        Some(path) => config::open_at(path),
+        None => config::open(),
+    }
+}
 
-    Ok(dicts)
+/// Get the fuzzy suggestions of a dictionary, or warn and get none if it is broken.
+fn fuzzy_suggestions<'a>(
+    d: &'a dyn Dictionary,
+    word: &str,
+    ranking: &Ranking,
+    history: &HashSet<String>,
+) -> Vec<Entry<'a>> {
+    skip_broken(d, d.fuzzy_lookup_ranked(word, ranking, history)).unwrap_or_default()
 }
 
-/// Look up a word with many flags.
+/// Load the configuration of the stardicts by their bookname.
+fn load_config(data_dirs: &DataDirs) -> Result<HashMap<String, DictConfig>> {
+    config::load(&open_config(data_dirs)?)
+}
+
+/// Sort the dictionaries in the configured search order.
+fn sort_dicts(dicts: &mut [Box<dyn Dictionary>], config: &HashMap<String, DictConfig>) {
+    dicts.sort_by_key(|d| {
+        config
+            .get(d.dict_name())
+            .and_then(|c| c.position)
+            .unwrap_or(usize::MAX)
+    });
+}
+
+/// Is the dictionary with this bookname called `name`, by its bookname or by its alias?
+fn is_named(bookname: &str, name: &str, config: &HashMap<String, DictConfig>) -> bool {
+    bookname.eq_ignore_ascii_case(name)
+        || config
+            .get(bookname)
+            .and_then(|c| c.alias.as_deref())
+            .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+}
+
+/// Load the dictionary at `path`, or the enabled dictionaries in the data directories.
 ///
-/// # Params
-/// - `online`: use online dictionary?
-/// - `local_first`: Try offline dictionary first, then the online?
-/// - `exact`: disable fuzzy searching?
-/// - `word`: the word being looked up.
-/// - `path`: the path of the stardict directory.
-/// - `read_aloud`: play word pronunciation?
+/// `path` may also be the name or the alias of a dictionary. If `names` is not empty,
+/// only the dictionaries with these names or aliases are kept, even if disabled.
+fn load_dicts(
+    path: &Option<String>,
+    names: &[String],
+    data_dirs: &DataDirs,
+) -> Result<Vec<Box<dyn Dictionary>>> {
+    if let Some(path) = path.as_ref().filter(|path| Path::new(path).is_dir()) {
+        let d = dictionary::open(Path::new(path))?;
+        print_warnings(&*d);
+        return Ok(vec![d]);
+    }
+
+    let config = load_config(data_dirs)?;
+    let mut dicts: Vec<_> = data_dirs
+        .find_dicts()?
+        .into_iter()
+        .filter(|path| !treedict::is_tree_dict(path))
+        .filter_map(|path| {
+            dictionary::open(&path)
+                .map_err(|e| warn_broken(&path, &e))
+                .ok()
+        })
+        .inspect(|d| print_warnings(&**d))
+        .collect();
+    sort_dicts(&mut dicts, &config);
+
+    let names: Vec<_> = path.iter().chain(names).collect();
+    if names.is_empty() {
+        dicts.retain(|d| config.get(d.dict_name()).is_none_or(|c| c.enabled));
+        return Ok(dicts);
+    }
+    if let Some(name) = names
+        .iter()
+        .find(|name| !dicts.iter().any(|d| is_named(d.dict_name(), name, &config)))
+    {
+        return Err(anyhow!("Couldn't find dictionary {:?}", name));
+    }
+    dicts.retain(|d| {
+        names
+            .iter()
+            .any(|name| is_named(d.dict_name(), name, &config))
+    });
+    Ok(dicts)
+}
+
+/// Print the entries found in a stardict under its name.
+fn print_section(dict_name: &str, entries: &[String]) {
+    println!("{}", style(dict_name).bold().underlined());
+    println!("{}\n", entries.join("\n\n"));
+}
+
+/// Highlight the occurrences of `phrase` in `text`, ignoring the case of ASCII letters.
+fn highlight(text: &str, phrase: &str) -> String {
+    if phrase.is_empty() {
+        return text.to_owned();
+    }
+    // The ASCII lower case has the same byte offsets.
+    let lower = text.to_ascii_lowercase();
+    let mut highlighted = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, m) in lower.match_indices(&phrase.to_ascii_lowercase()) {
+        let end = start + m.len();
+        highlighted.push_str(&text[last..start]);
+        highlighted.push_str(&style(&text[start..end]).red().bold().to_string());
+        last = end;
+    }
+    highlighted.push_str(&text[last..]);
+    highlighted
+}
+
+/// The flags of [query].
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Use online dictionary?
+    pub online: bool,
+    /// Try offline dictionary first, then the online?
+    pub local_first: bool,
+    /// Disable fuzzy searching?
+    pub exact: bool,
+    /// The path of the stardict directory.
+    pub path: Option<String>,
+    /// Play word pronunciation?
+    pub read_aloud: bool,
+    /// How to rank the suggestions of fuzzy searching.
+    pub ranking: Ranking,
+    /// Show the entries of the first or of all the stardicts containing the word.
+    pub scope: Scope,
+    /// Only search the stardicts with these names, if any.
+    pub dicts: Vec<String>,
+    /// Where to find the stardicts.
+    pub data_dirs: DataDirs,
+}
+
+/// Look up a word with many flags, see [QueryOptions].
 ///
 /// ## Word prefix
 /// - `/terraria`: enable fuzzy searching.
 /// - `|terraria`: disable fuzzy searching.
 /// - `@terraria`: use online dictionary.
-pub fn query(
-    online: bool,
-    local_first: bool,
-    exact: bool,
-    word: String,
-    path: &Option<String>,
-    read_aloud: bool,
-) -> Result<()> {
+/// - `+泰拉瑞亚`: search the definitions of all dictionaries, Latin words only as a whole.
+/// - `terr*ia`, `terrari?`: choose from the headwords matching a glob.
+/// - `:^terr.*a$`: choose from the headwords matching a regular expression.
+pub fn query(word: String, options: &QueryOptions) -> Result<()> {
+    let QueryOptions {
+        online,
+        local_first,
+        exact,
+        path,
+        read_aloud,
+        ranking,
+        scope,
+        dicts: names,
+        data_dirs,
+    } = options;
     let mut word = word.as_str();
     let mut corrected_word: Option<String> = None;
-    let online = word.chars().next().map_or(online, |c| {
+    // The pronunciation found in the local stardicts, played instead of the online one.
+    let mut local_sound: Option<Vec<u8>> = None;
+    let online = word.chars().next().map_or(*online, |c| {
         if c == '@' {
             word = &word[1..];
             true
         } else {
-            online
+            *online
         }
     });
 
@@ -102,7 +255,7 @@ pub fn query(
             word = &word[1..];
             false
         }
-        _ => exact,
+        _ => *exact,
     };
 
     let len = word.len();
@@ -111,35 +264,81 @@ pub fn query(
             word = &word[..len - 1];
             true
         }
-        _ => read_aloud,
+        _ => *read_aloud,
     };
 
     if online {
         // only use online dictionary
         lookup_online(word)?;
-    } else {
-        let mut dicts = Vec::new();
-        if let Some(path) = path {
-            dicts.push(StarDict::new(path.into())?);
-        } else {
-            for d in get_dicts_entries()? {
-                dicts.push(StarDict::new(d.path())?);
+    } else if let Some(phrase) = word.strip_prefix('+') {
+        for d in load_dicts(path, names, data_dirs)? {
+            let Some(entries) = skip_broken(&*d, d.fulltext_search(phrase)) else {
+                continue;
+            };
+            if entries.is_empty() {
+                eprintln!("Found nothing in {}", d.dict_name());
+                continue;
             }
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|e| format!("{}\n{}", e.word, highlight(&e.trans, phrase)))
+                .collect();
+            print_section(d.dict_name(), &entries);
         }
-
+    } else if let Some(pattern) = Pattern::parse(word)? {
+        let dicts = load_dicts(path, names, data_dirs)?;
+        let mut matches = Vec::new();
+        for d in &dicts {
+            let words = skip_broken(&**d, d.pattern_search(&pattern)).unwrap_or_default();
+            matches.extend(words.into_iter().map(|w| (d, w)));
+        }
+        if matches.is_empty() {
+            eprintln!("Found nothing matching {}", word);
+        } else if let Some(selection) = Select::with_theme(&ColorfulTheme::default())
+            .items(
+                &matches
+                    .iter()
+                    .map(|(d, w)| format!("{} ({})", w, d.dict_name()))
+                    .collect::<Vec<_>>(),
+            )
+            .default(0)
+            .interact_on_opt(&Term::stderr())?
+        {
+            let (d, w) = matches[selection];
+            let entries: Vec<_> = d
+                .exact_lookup_all(w)?
+                .iter()
+                .map(|e| e.to_string())
+                .collect();
+            println!("{}", entries.join("\n\n"));
+            corrected_word = Some(w.to_owned());
+        }
+    } else {
+        let dicts = load_dicts(path, names, data_dirs)?;
         let mut found = false;
         for d in &dicts {
-            match d.exact_lookup(word) {
-                Some(entry) => {
-                    println!("{}\n{}", entry.word, entry.trans);
-                    found = true;
+            let Some(entries) = skip_broken(&**d, d.exact_lookup_all(word)) else {
+                continue;
+            };
+            if entries.is_empty() {
+                eprintln!("Found nothing in {}", d.dict_name());
+                continue;
+            }
+            if read_aloud && local_sound.is_none() {
+                local_sound = entries.iter().find_map(|e| d.sound(e).ok().flatten());
+            }
+            let entries: Vec<_> = entries.iter().map(|e| e.to_string()).collect();
+            found = true;
+            match scope {
+                Scope::First => {
+                    println!("{}", entries.join("\n\n"));
                     break;
                 }
-                _ => eprintln!("Found nothing in {}", d.dict_name()),
+                Scope::All => print_section(d.dict_name(), &entries),
             }
         }
 
-        if !found && local_first {
+        if !found && *local_first {
             if lookup_online(word).is_ok() {
                 found = true;
             } else {
@@ -154,15 +353,26 @@ pub fn query(
                 .default(0)
                 .interact_on_opt(&Term::stderr())?
             {
-                if let Some(entries) = dicts[selection].fuzzy_lookup(word) {
+                let history = if ranking.history > 0.0 {
+                    history::history_words().unwrap_or_default()
+                } else {
+                    HashSet::new()
+                };
+                let entries = fuzzy_suggestions(&*dicts[selection], word, ranking, &history);
+                if !entries.is_empty() {
                     if let Some(sub_selection) = Select::with_theme(&ColorfulTheme::default())
-                        .items(&entries.iter().map(|x| x.word).collect::<Vec<&str>>())
+                        .items(
+                            &entries
+                                .iter()
+                                .map(|x| x.synonym.unwrap_or(x.word))
+                                .collect::<Vec<&str>>(),
+                        )
                         .default(0)
                         .interact_on_opt(&Term::stderr())?
                     {
                         let entry = &entries[sub_selection];
                         corrected_word = Some(entry.word.to_owned());
-                        println!("{}\n{}", entry.word, entry.trans);
+                        println!("{}", entry);
                     }
                 }
             }
@@ -170,6 +380,9 @@ pub fn query(
     }
 
     if read_aloud {
+        if let Some(sound) = local_sound {
+            return dict::play(sound);
+        }
         if let Some(corrected_word) = &corrected_word {
             word = corrected_word;
         }
@@ -180,20 +393,14 @@ pub fn query(
 }
 
 /// Look up a word with many flags interactively using [query].
-pub fn repl(
-    online: bool,
-    local_first: bool,
-    exact: bool,
-    path: &Option<String>,
-    read_aloud: bool,
-) -> Result<()> {
+pub fn repl(options: &QueryOptions) -> Result<()> {
     let mut rl = rustyline::DefaultEditor::new().with_context(|| "Failed to read lines")?;
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(word) => {
                 let _ = rl.add_history_entry(&word);
-                if let Err(e) = query(online, local_first, exact, word, path, read_aloud) {
+                if let Err(e) = query(word, options) {
                     println!("{:?}", e);
                 }
             }
@@ -204,22 +411,524 @@ pub fn repl(
     }
 }
 
-/// List stardicts in the dioxionary config path.
-pub fn list_dicts() -> Result<()> {
+/// List the headwords matching a pattern in every dictionary.
+///
+/// A pattern without wildcards or the `:` regex prefix matches the headwords starting with it.
+pub fn search(pattern: &str, path: &Option<String>, data_dirs: &DataDirs) -> Result<()> {
+    let pattern =
+        Pattern::parse(pattern)?.unwrap_or_else(|| Pattern::Glob(format!("{}*", pattern)));
+    let mut found = false;
+    for d in load_dicts(path, &[], data_dirs)? {
+        let words = skip_broken(&*d, d.pattern_search(&pattern)).unwrap_or_default();
+        if !words.is_empty() {
+            println!("{}", d.dict_name());
+            words.iter().for_each(|w| println!("  {}", w));
+            found = true;
+        }
+    }
+    if !found {
+        return Err(anyhow!("Found nothing matching the pattern"));
+    }
+    Ok(())
+}
+
+/// Format a file size in bytes for people.
+fn format_size(size: u64) -> String {
+    let mut size = size as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit).replace(".0 B", " B");
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
+/// List the dictionaries in the data directories in the search order, with their health.
+///
+/// The tree dictionaries, which aren't searched, come after them, and the dictionaries
+/// which can't be opened at all come last, under their directory name.
+/// With `long`, the version, the number of synonyms and the file formats are shown too.
+pub fn list_dicts(data_dirs: &DataDirs, long: bool) -> Result<()> {
+    let config = load_config(data_dirs)?;
+    let mut dicts = Vec::new();
+    let mut trees = Vec::new();
+    let mut broken = Vec::new();
+    for path in data_dirs.find_dicts()? {
+        if treedict::is_tree_dict(&path) {
+            match treedict::read_ifo(&path) {
+                Ok(ifo) => trees.push(ifo),
+                Err(e) => broken.push((path, e)),
+            }
+            continue;
+        }
+        match dictionary::open(&path) {
+            Ok(d) => dicts.push(d),
+            Err(e) => broken.push((path, e)),
+        }
+    }
+    sort_dicts(&mut dicts, &config);
+
+    let mut header = vec!["Dictionary's name", "Alias", "Enabled", "Word count"];
+    if long {
+        header.extend(["Version", "Synonym count", "Format"]);
+    }
+    header.push("Health");
     let mut table: Table = Table::new();
-    table.add_row(Row::new(vec![
-        Cell::new("Dictionary's name").with_style(Attr::Bold),
-        Cell::new("Word count").with_style(Attr::Bold),
-    ]));
-    get_dicts_entries()?.into_iter().for_each(|x| {
-        if let Ok(stardict) = StarDict::new(x.path()) {
-            let row = Row::new(vec![
-                Cell::new(stardict.dict_name()),
-                Cell::new(stardict.wordcount().to_string().as_str()),
+    table.add_row(Row::new(
+        header
+            .iter()
+            .map(|title| Cell::new(title).with_style(Attr::Bold))
+            .collect(),
+    ));
+    for stardict in &dicts {
+        let dict_config = config
+            .get(stardict.dict_name())
+            .cloned()
+            .unwrap_or_default();
+        let health = match stardict.check() {
+            Ok(()) => "ok".to_owned(),
+            Err(e) => format!("broken: {:#}", e),
+        };
+        let mut row = vec![
+            Cell::new(stardict.dict_name()),
+            Cell::new(dict_config.alias.as_deref().unwrap_or_default()),
+            Cell::new(if dict_config.enabled { "yes" } else { "no" }),
+            Cell::new(stardict.wordcount().to_string().as_str()),
+        ];
+        if long {
+            row.extend([
+                Cell::new(&stardict.version()),
+                Cell::new(stardict.synwordcount().to_string().as_str()),
+                Cell::new(&stardict.details()),
             ]);
-            table.add_row(row);
         }
-    });
+        row.push(Cell::new(&health));
+        table.add_row(Row::new(row));
+    }
+    for ifo in &trees {
+        let alias = config.get(&ifo.bookname).and_then(|c| c.alias.clone());
+        let mut row = vec![
+            Cell::new(&ifo.bookname),
+            Cell::new(alias.as_deref().unwrap_or_default()),
+            Cell::new(""),
+            Cell::new(ifo.wordcount.to_string().as_str()),
+        ];
+        row.resize(header.len() - 1, Cell::new(""));
+        row.push(Cell::new("tree dictionary"));
+        table.add_row(Row::new(row));
+    }
+    for (path, e) in &broken {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut row = vec![Cell::new(&name)];
+        row.resize(header.len() - 1, Cell::new(""));
+        row.push(Cell::new(&format!("broken: {:#}", e)));
+        table.add_row(Row::new(row));
+    }
     table.printstd();
     Ok(())
 }
+
+/// Get the directories and the booknames of the dictionaries which can be opened, tree
+/// dictionaries included.
+fn named_dicts(paths: Vec<PathBuf>) -> Vec<(PathBuf, String)> {
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let bookname = if treedict::is_tree_dict(&path) {
+                treedict::read_ifo(&path).ok()?.bookname
+            } else {
+                dictionary::open(&path).ok()?.dict_name().to_owned()
+            };
+            Some((path, bookname))
+        })
+        .collect()
+}
+
+/// Get the booknames of the stardicts with the given names or aliases.
+fn resolve_dict_names(names: &[String], data_dirs: &DataDirs) -> Result<Vec<String>> {
+    let config = load_config(data_dirs)?;
+    let dicts = named_dicts(data_dirs.find_dicts()?);
+    names
+        .iter()
+        .map(|name| {
+            dicts
+                .iter()
+                .find(|(_, bookname)| is_named(bookname, name, &config))
+                .map(|(_, bookname)| bookname.clone())
+                .with_context(|| format!("Couldn't find dictionary {:?}", name))
+        })
+        .collect()
+}
+
+/// Enable or disable stardicts by name or alias.
+pub fn enable_dicts(names: &[String], enabled: bool, data_dirs: &DataDirs) -> Result<()> {
+    let conn = open_config(data_dirs)?;
+    for name in resolve_dict_names(names, data_dirs)? {
+        config::set_enabled(&conn, &name, enabled)?;
+    }
+    Ok(())
+}
+
+/// Search the stardicts with these names or aliases first, in this order.
+pub fn order_dicts(names: &[String], data_dirs: &DataDirs) -> Result<()> {
+    config::set_order(
+        &mut open_config(data_dirs)?,
+        &resolve_dict_names(names, data_dirs)?,
+    )
+}
+
+/// Set the alias of a stardict, or remove it if `alias` is `None`.
+pub fn alias_dict(name: &str, alias: Option<&str>, data_dirs: &DataDirs) -> Result<()> {
+    let name = resolve_dict_names(&[name.to_owned()], data_dirs)?.remove(0);
+    if let Some(alias) = alias {
+        if let Ok(other) = resolve_dict_names(&[alias.to_owned()], data_dirs) {
+            if other[0] != name {
+                return Err(anyhow!(
+                    "The alias {:?} is already used by {}",
+                    alias,
+                    other[0]
+                ));
+            }
+        }
+    }
+    config::set_alias(&open_config(data_dirs)?, &name, alias)
+}
+
+/// Get the directories of the stardicts with the given names, aliases or paths.
+///
+/// Paths are also accepted for stardicts outside the data directories or too broken to
+/// have a name.
+fn find_dict_paths(names: &[String], data_dirs: &DataDirs) -> Result<Vec<PathBuf>> {
+    let config = load_config(data_dirs)?;
+    let dicts = named_dicts(data_dirs.find_dicts().unwrap_or_default());
+    names
+        .iter()
+        .map(|name| {
+            match dicts
+                .iter()
+                .find(|(_, bookname)| is_named(bookname, name, &config))
+            {
+                Some((path, _)) => Ok(path.clone()),
+                None if Path::new(name).is_dir() => Ok(PathBuf::from(name)),
+                None => Err(anyhow!("Couldn't find dictionary {:?}", name)),
+            }
+        })
+        .collect()
+}
+
+/// Verify the dictionaries with these names, aliases or paths, or all of them, and print
+/// the report in JSON.
+///
+/// Fails if any dictionary has a problem, after printing the report.
+pub fn verify_dicts(names: &[String], data_dirs: &DataDirs) -> Result<()> {
+    let paths = if names.is_empty() {
+        let mut paths = data_dirs.find_dicts()?;
+        paths.retain(|path| !treedict::is_tree_dict(path));
+        paths
+    } else {
+        find_dict_paths(names, data_dirs)?
+    };
+
+    let reports: Vec<_> = paths
+        .into_iter()
+        .map(|path| match dictionary::open(&path) {
+            Ok(d) => d.verify(),
+            Err(e) => Report {
+                path,
+                bookname: None,
+                problems: vec![Problem::new("open", format!("{:#}", e))],
+            },
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&reports)?);
+
+    let broken = reports.iter().filter(|r| !r.problems.is_empty()).count();
+    if broken > 0 {
+        return Err(anyhow!(
+            "Found problems in {} of {} dictionaries",
+            broken,
+            reports.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Show the metadata and the files of the dictionary with this name, alias or path.
+pub fn show_info(name: &str, data_dirs: &DataDirs) -> Result<()> {
+    let path = find_dict_paths(&[name.to_owned()], data_dirs)?.remove(0);
+    let d = dictionary::open(&path)?;
+
+    let mut table = Table::new();
+    let mut add = |key: &str, value: &str| {
+        if !value.is_empty() {
+            table.add_row(Row::new(vec![
+                Cell::new(key).with_style(Attr::Bold),
+                Cell::new(value),
+            ]));
+        }
+    };
+    add("Name", d.dict_name());
+    add("Path", &d.path().to_string_lossy());
+    add("Version", &d.version());
+    add("Word count", &d.wordcount().to_string());
+    add("Synonym count", &d.synwordcount().to_string());
+    for (key, value) in d.metadata() {
+        add(key, &value);
+    }
+    add("Format", &d.details());
+    for file in d.files() {
+        let size = std::fs::metadata(file)
+            .map(|meta| format_size(meta.len()))
+            .unwrap_or_else(|e| e.to_string());
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        add(&name, &size);
+    }
+    table.printstd();
+
+    let description = d.description();
+    if !description.is_empty() {
+        let br = regex::Regex::new(r"(?i)<br\s*/?>").expect("valid regex");
+        println!("\n{}", br.replace_all(description.trim(), "\n"));
+    }
+    Ok(())
+}
+
+/// Build a stardict from a glossary into the directory `output`, by default named after
+/// the glossary in the current directory.
+///
+/// The metadata, such as the bookname, is taken from `builder`.
+pub fn build_dict(
+    input: &Path,
+    output: Option<&Path>,
+    format: Option<SourceFormat>,
+    mut builder: StarDictBuilder,
+) -> Result<()> {
+    let name = input
+        .file_stem()
+        .with_context(|| format!("Invalid glossary path {:?}", input))?
+        .to_string_lossy()
+        .into_owned();
+    let format = format
+        .or_else(|| build::guess_format(input))
+        .with_context(|| format!("Couldn't guess the format of {:?}, use --format", input))?;
+    if builder.bookname.is_empty() {
+        builder.bookname = name.clone();
+    }
+
+    let file = std::fs::File::open(input)
+        .with_context(|| format!("Failed to open glossary {:?}", input))?;
+    build::read_source(file, format, &mut builder)
+        .with_context(|| format!("Failed to read glossary {:?}", input))?;
+    let output = output.map_or_else(|| PathBuf::from(&name), Path::to_owned);
+    builder.write(&output, &name)?;
+    println!("Built {} in {:?}", builder.bookname, output);
+    Ok(())
+}
+
+/// List the resources of the dictionary with this name, alias or path without `keys` and
+/// `output`, or extract them into `output` or play them.
+///
+/// All the resources are extracted without `keys`, but playing needs `keys`.
+pub fn dict_resources(
+    name: &str,
+    keys: &[String],
+    output: Option<&Path>,
+    play: bool,
+    data_dirs: &DataDirs,
+) -> Result<()> {
+    let path = find_dict_paths(&[name.to_owned()], data_dirs)?.remove(0);
+    let d = dictionary::open(&path)?;
+    if keys.is_empty() && play {
+        return Err(anyhow!("--play needs at least one key"));
+    }
+    if keys.is_empty() && output.is_none() {
+        let keys = d.resource_keys()?;
+        if keys.is_empty() {
+            return Err(anyhow!("Found no resources in {}", d.dict_name()));
+        }
+        keys.iter().for_each(|key| println!("{}", key));
+        return Ok(());
+    }
+
+    let keys = if keys.is_empty() {
+        d.resource_keys()?
+    } else {
+        keys.to_vec()
+    };
+    for key in &keys {
+        let data = d
+            .resource(key)?
+            .with_context(|| format!("Couldn't find resource {:?} in {}", key, d.dict_name()))?;
+        if play {
+            dict::play(data)?;
+        } else if let Some(output) = output {
+            // Keep the directories of the keys, but never write outside of `output`.
+            if !resource::is_safe_key(key) {
+                return Err(anyhow!("Invalid resource key {:?}", key));
+            }
+            let path = output.join(key);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create directory {:?}", dir))?;
+            }
+            std::fs::write(&path, data).with_context(|| format!("Failed to write {:?}", path))?;
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Print the path from the root to the last node of `path`, and the node's definition.
+fn print_tree_node(tree: &TreeDict, path: &[&Node]) -> Result<()> {
+    let breadcrumb: Vec<_> = path.iter().map(|node| node.word.as_str()).collect();
+    println!("{}", style(breadcrumb.join(" > ")).bold().underlined());
+    if let Some(definition) = tree.definition(path[path.len() - 1])? {
+        println!("{}", definition);
+    }
+    println!();
+    Ok(())
+}
+
+/// Choose nodes of a tree dictionary one level at a time, starting under `path`, and
+/// print their definitions, until cancelled.
+fn browse_tree<'a>(tree: &'a TreeDict, mut path: Vec<&'a Node>) -> Result<()> {
+    loop {
+        let children = path.last().copied().map_or(tree.roots(), |n| &n.children);
+        let mut items: Vec<_> = children
+            .iter()
+            .map(|n| match n.children.is_empty() {
+                true => n.word.clone(),
+                false => format!("{}/", n.word),
+            })
+            .collect();
+        if !path.is_empty() {
+            items.insert(0, "..".to_owned());
+        }
+        if items.is_empty() {
+            return Ok(());
+        }
+        let prompt = match path.last() {
+            Some(node) => node.word.as_str(),
+            None => tree.dict_name(),
+        };
+        let Some(mut selection) = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .items(&items)
+            .default(0)
+            .interact_on_opt(&Term::stderr())?
+        else {
+            return Ok(());
+        };
+        if !path.is_empty() {
+            if selection == 0 {
+                path.pop();
+                continue;
+            }
+            selection -= 1;
+        }
+
+        let node = &children[selection];
+        path.push(node);
+        print_tree_node(tree, &path)?;
+        if node.children.is_empty() {
+            path.pop();
+        }
+    }
+}
+
+/// Print the hierarchy of the tree dictionary with this name, alias or path, or the
+/// definition and the subtree of one of its nodes, down to `depth` levels below it.
+///
+/// With `browse`, the nodes are chosen interactively instead.
+pub fn tree_dict(
+    name: &str,
+    node: Option<&str>,
+    depth: Option<usize>,
+    browse: bool,
+    data_dirs: &DataDirs,
+) -> Result<()> {
+    let path = find_dict_paths(&[name.to_owned()], data_dirs)?.remove(0);
+    let tree = TreeDict::new(&path)?;
+    let path = match node {
+        Some(word) => tree
+            .find(word)
+            .with_context(|| format!("Couldn't find {:?} in {}", word, tree.dict_name()))?,
+        None => Vec::new(),
+    };
+    if browse {
+        return browse_tree(&tree, path);
+    }
+
+    if !path.is_empty() {
+        print_tree_node(&tree, &path)?;
+    }
+    let children = path.last().copied().map_or(tree.roots(), |n| &n.children);
+    for (level, node) in children.iter().flat_map(Node::walk) {
+        if depth.is_none_or(|depth| level < depth) {
+            println!("{}{}", "  ".repeat(level), node.word);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::test::tempdir;
+    use std::fs::write;
+
+    #[test]
+    fn query_skips_broken_dicts() {
+        let root = tempdir();
+        for name in ["a-broken", "b-good"] {
+            let mut builder = StarDictBuilder::new(name);
+            builder.add("rust", "铁锈");
+            builder.write(&root.path().join(name), name).unwrap();
+        }
+        write(root.path().join("a-broken/a-broken.idx"), b"rust").unwrap();
+
+        let data_dirs = DataDirs {
+            dirs: vec![root.path().to_owned()],
+            only: true,
+            config: Some(root.path().join("config.db")),
+        };
+        let dicts = load_dicts(&None, &[], &data_dirs).unwrap();
+        assert_eq!(dicts.len(), 2);
+        assert!(skip_broken(&*dicts[0], dicts[0].exact_lookup_all("rust")).is_none());
+        let ranking = Ranking::default();
+        assert!(fuzzy_suggestions(&*dicts[0], "rst", &ranking, &HashSet::new()).is_empty());
+        let entries = fuzzy_suggestions(&*dicts[1], "rst", &ranking, &HashSet::new());
+        assert_eq!(entries[0].word, "rust");
+
+        let options = QueryOptions {
+            online: false,
+            local_first: false,
+            exact: true,
+            path: None,
+            read_aloud: false,
+            ranking: Ranking::default(),
+            scope: Scope::First,
+            dicts: Vec::new(),
+            data_dirs,
+        };
+        assert!(query("rust".to_owned(), &options).is_ok());
+    }
+
+    #[test]
+    fn play_needs_keys() {
+        let root = tempdir();
+        let mut builder = StarDictBuilder::new("sounds");
+        builder.add("rust", "铁锈");
+        builder
+            .write(&root.path().join("sounds"), "sounds")
+            .unwrap();
+        let data_dirs = DataDirs {
+            dirs: vec![root.path().to_owned()],
+            only: true,
+            config: Some(root.path().join("config.db")),
+        };
+        let err = dict_resources("sounds", &[], None, true, &data_dirs).unwrap_err();
+        assert_eq!(err.to_string(), "--play needs at least one key");
+    }
+}
@@ -0,0 +1,154 @@
+//! Decompression of [LZO1X](https://www.oberhumer.com/opensource/lzo/) data, used by
+//! the record blocks of some MDict files.
+//!
+//! The stream is a sequence of instructions, each copying literal bytes from the input
+//! or a match from the output already decompressed, and ends with a match of distance
+//! 0x4000 and length 3.
+use anyhow::{anyhow, Result};
+
+/// The distance added to the matches of an `M1` instruction following a literal run.
+const M2_MAX_OFFSET: usize = 0x0800;
+
+/// Read the input of an LZO1X stream.
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Input<'_> {
+    fn byte(&mut self) -> Result<usize> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Truncated LZO data"))?;
+        self.pos += 1;
+        Ok(b as usize)
+    }
+
+    fn le16(&mut self) -> Result<usize> {
+        Ok(self.byte()? | self.byte()? << 8)
+    }
+
+    /// Read the rest of a length whose low bits were zero: a 0 byte adds 255.
+    fn long_length(&mut self, base: usize) -> Result<usize> {
+        let mut length = base;
+        loop {
+            match self.byte()? {
+                0 => length += 255,
+                b => return Ok(length + b),
+            }
+        }
+    }
+
+    fn literal(&mut self, len: usize, output: &mut Vec<u8>) -> Result<()> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow!("Truncated LZO data"))?;
+        output.extend_from_slice(bytes);
+        self.pos += len;
+        Ok(())
+    }
+}
+
+/// Copy `len` bytes from `distance` bytes back in the output, which may overlap.
+fn copy_match(output: &mut Vec<u8>, distance: usize, len: usize) -> Result<()> {
+    let start = output
+        .len()
+        .checked_sub(distance)
+        .ok_or_else(|| anyhow!("Invalid LZO match distance {}", distance))?;
+    for i in start..start + len {
+        output.push(output[i]);
+    }
+    Ok(())
+}
+
+/// Decompress LZO1X data, `size_hint` being the expected size of the output.
+pub fn decompress(data: &[u8], size_hint: usize) -> Result<Vec<u8>> {
+    let mut input = Input { data, pos: 0 };
+    let mut output = Vec::with_capacity(size_hint);
+    // The number of literals copied by the last instruction, 4 standing for a literal run.
+    let mut state = 0;
+
+    if data.first().is_some_and(|&b| b > 17) {
+        let t = input.byte()? - 17;
+        input.literal(t, &mut output)?;
+        state = if t < 4 { t } else { 4 };
+    }
+
+    loop {
+        let t = input.byte()?;
+        let (distance, len, next) = if t < 16 {
+            if state == 0 {
+                // A run of literals.
+                let len = if t == 0 { input.long_length(15)? } else { t };
+                input.literal(len + 3, &mut output)?;
+                state = 4;
+                continue;
+            }
+            let b = input.byte()?;
+            if state < 4 {
+                (1 + (t >> 2) + (b << 2), 2, t & 3)
+            } else {
+                (1 + M2_MAX_OFFSET + (t >> 2) + (b << 2), 3, t & 3)
+            }
+        } else if t >= 64 {
+            let b = input.byte()?;
+            (1 + ((t >> 2) & 7) + (b << 3), (t >> 5) + 1, t & 3)
+        } else if t >= 32 {
+            let len = match t & 31 {
+                0 => input.long_length(31)?,
+                n => n,
+            } + 2;
+            let n = input.le16()?;
+            (1 + (n >> 2), len, n & 3)
+        } else {
+            let len = match t & 7 {
+                0 => input.long_length(7)?,
+                n => n,
+            } + 2;
+            let n = input.le16()?;
+            let distance = ((t & 8) << 11) + (n >> 2);
+            if distance == 0 {
+                if len != 3 {
+                    return Err(anyhow!("Invalid end of LZO data"));
+                }
+                return Ok(output);
+            }
+            (distance + 0x4000, len, n & 3)
+        };
+        copy_match(&mut output, distance, len)?;
+        input.literal(next, &mut output)?;
+        state = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::decompress;
+
+    #[test]
+    fn decompress_lzo1x() {
+        // 4 literals, a match of 8 bytes 4 bytes back, then the end.
+        let data = [21, b'a', b'b', b'c', b'd', 236, 0, 0x11, 0, 0];
+        assert_eq!(decompress(&data, 12).unwrap(), b"abcdabcdabcd");
+
+        // A run of 3 + 15 + 255 + 3 literals, a match of 2 + 31 + 5 bytes 2 back with
+        // 1 trailing literal, a match of 2 bytes 1 back with 1 trailing literal, the end.
+        let literals: Vec<u8> = (0..276).map(|i| (i % 251) as u8).collect();
+        let mut data = vec![0, 0, 3];
+        data.extend(&literals);
+        data.extend([32, 5, 0x05, 0x00, b'x']);
+        data.extend([0x01, 0x00, b'y']);
+        data.extend([0x11, 0, 0]);
+        let mut expected = literals;
+        for _ in 0..38 {
+            expected.push(expected[expected.len() - 2]);
+        }
+        expected.extend(b"xxxy");
+        assert_eq!(decompress(&data, 0).unwrap(), expected);
+
+        assert!(decompress(&data[..20], 0).is_err());
+        assert!(decompress(&[17, 0x40, 0xff], 0).is_err());
+    }
+}
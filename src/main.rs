@@ -3,11 +3,35 @@
 use anyhow::Result;
 use clap::CommandFactory;
 use dioxionary::{
-    cli::{Action, Cli, Parser},
-    history, list_dicts, query, repl,
+    alias_dict, build_dict,
+    cli::{Action, Cli, DictsAction, Parser},
+    datadir::DataDirs,
+    dict_resources, enable_dicts, history, list_dicts, order_dicts, query, repl, search, show_info,
+    stardict::StarDictBuilder,
+    tree_dict, verify_dicts, QueryOptions,
 };
 use std::env;
 
+/// Look up the words, or enter the interactive mode without words.
+fn lookup(word: Option<Vec<String>>, options: &QueryOptions) -> Result<()> {
+    if let Some(word_list) = word {
+        let mut found = false;
+        word_list.into_iter().for_each(|word| {
+            if let Err(e) = query(word, options) {
+                eprintln!("{:?}", e);
+            } else {
+                found = true;
+            }
+        });
+        if !found {
+            std::process::exit(1);
+        }
+        Ok(())
+    } else {
+        repl(options)
+    }
+}
+
 fn main() -> Result<()> {
     let cli: Cli = Cli::parse();
 
@@ -17,58 +41,43 @@ fn main() -> Result<()> {
         std::process::exit(0);
     }
 
+    let data_dirs = DataDirs::from(&cli);
     if let Some(action) = cli.action {
         match action {
             Action::Count => history::count_history(),
             Action::List(t) => history::list_history(t.type_, t.sort, t.table, t.column),
             Action::Lookup(w) => {
-                let online = w.online;
-                let local_first = w.local_first;
-                let exact = w.exact_search;
-                let word = w.word;
-                let path = &w.local;
-                let read_aloud = w.read_aloud;
-                if let Some(word_list) = word {
-                    let mut found = false;
-                    word_list.into_iter().for_each(|word| {
-                        if let Err(e) = query(online, local_first, exact, word, path, read_aloud) {
-                            eprintln!("{:?}", e);
-                        } else {
-                            found = true;
-                        }
-                    });
-                    if !found {
-                        std::process::exit(1);
-                    }
-                    Ok(())
-                } else {
-                    repl(online, local_first, exact, path, read_aloud)
-                }
+                let options = QueryOptions {
+                    data_dirs,
+                    ..QueryOptions::from(&w)
+                };
+                lookup(w.word, &options)
             }
-            Action::Dicts => list_dicts(),
-        }
-    } else {
-        let online = cli.online;
-        let local_first = cli.local_first;
-        let exact = cli.exact_search;
-        let word = cli.word;
-        let path = &cli.local;
-        let read_aloud = cli.read_aloud;
-        if let Some(word_list) = word {
-            let mut found = false;
-            word_list.into_iter().for_each(|word| {
-                if let Err(e) = query(online, local_first, exact, word, path, read_aloud) {
-                    eprintln!("{:?}", e);
-                } else {
-                    found = true;
+            Action::Dicts(d) => match d.action {
+                None => list_dicts(&data_dirs, d.long),
+                Some(DictsAction::Enable { names }) => enable_dicts(&names, true, &data_dirs),
+                Some(DictsAction::Disable { names }) => enable_dicts(&names, false, &data_dirs),
+                Some(DictsAction::Order { names }) => order_dicts(&names, &data_dirs),
+                Some(DictsAction::Alias { name, alias }) => {
+                    alias_dict(&name, alias.as_deref(), &data_dirs)
                 }
-            });
-            if !found {
-                std::process::exit(1);
+                Some(DictsAction::Verify { names }) => verify_dicts(&names, &data_dirs),
+            },
+            Action::Search(s) => search(&s.pattern, &s.local, &data_dirs),
+            Action::Info(i) => show_info(&i.name, &data_dirs),
+            Action::Build(b) => {
+                let mut builder = StarDictBuilder::new(&b.bookname.unwrap_or_default());
+                builder.author = b.author.unwrap_or_default();
+                builder.description = b.description.unwrap_or_default();
+                build_dict(&b.input, b.output.as_deref(), b.format, builder)
             }
-            Ok(())
-        } else {
-            repl(online, local_first, exact, path, read_aloud)
+            Action::Res(r) => {
+                dict_resources(&r.name, &r.keys, r.output.as_deref(), r.play, &data_dirs)
+            }
+            Action::Tree(t) => tree_dict(&t.name, t.node.as_deref(), t.depth, t.browse, &data_dirs),
         }
+    } else {
+        let options = QueryOptions::from(&cli);
+        lookup(cli.word, &options)
     }
 }
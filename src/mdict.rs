@@ -0,0 +1,1028 @@
+//! Look up words from MDict dictionaries: an `.mdx` file of definitions and `.mdd` files
+//! of resources, such as pictures and sounds, next to it.
+//!
+//! An MDict file starts with a header of XML attributes in UTF-16, followed by the key
+//! section, listing the headwords with the offsets of their records, and the record
+//! section. Both sections are split into blocks, each stored as is or compressed with LZO
+//! or zlib. Only unencrypted files of the format versions 1 and 2 are supported.
+use crate::collate::ascii_casecmp;
+use crate::fulltext::FullTextIndex;
+use crate::fuzzy::{BkTree, Ranking};
+use crate::lzo;
+use crate::pattern::Pattern;
+use crate::stardict::{Entry, EntryPart, Problem, Report};
+use anyhow::{anyhow, Context, Result};
+use encoding_rs::{Encoding, GB18030, UTF_16LE, UTF_8};
+use flate2::read::ZlibDecoder;
+use std::cell::{OnceCell, RefCell};
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The prefix of a record redirecting to another headword.
+const LINK: &str = "@@@LINK=";
+
+/// The most redirections followed to find the record of a headword.
+const MAX_LINKS: usize = 5;
+
+/// The most a block can expand when decompressed, like deflate, to bound the memory
+/// reserved for the sizes given in the file.
+const MAX_EXPANSION: usize = 1032;
+
+/// Compute the Adler-32 checksum, which MDict uses for its headers and blocks.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // The sums can't overflow within 5552 bytes.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Read the numbers and the strings of a decompressed block.
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Bytes<'a> {
+        Bytes { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| anyhow!("Truncated MDict block"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read a big-endian number of `width` bytes.
+    fn number(&mut self, width: usize) -> Result<usize> {
+        let n = self
+            .take(width)?
+            .iter()
+            .fold(0u64, |n, &b| n << 8 | b as u64);
+        usize::try_from(n).map_err(|_| anyhow!("Invalid MDict number {}", n))
+    }
+
+    /// Read a string ending with a null character of `unit` bytes.
+    fn null_terminated(&mut self, unit: usize) -> Result<&'a [u8]> {
+        let rest = &self.data[self.pos..];
+        let len = rest
+            .chunks(unit)
+            .position(|c| c.iter().all(|&b| b == 0))
+            .ok_or_else(|| anyhow!("Unterminated MDict key"))?
+            * unit;
+        self.pos += len + unit;
+        Ok(&rest[..len])
+    }
+}
+
+/// Decompress a key or record block: the compression type, the checksum of the
+/// decompressed data and the data.
+fn decompress_block(block: &[u8], size: usize) -> Result<Vec<u8>> {
+    let (head, data) = (block.get(..8), block.get(8..));
+    let (Some(head), Some(data)) = (head, data) else {
+        return Err(anyhow!("Truncated MDict block"));
+    };
+    let decompressed = match head[0] {
+        0 => data.to_vec(),
+        1 => lzo::decompress(data, min(size, data.len().saturating_mul(MAX_EXPANSION)))?,
+        2 => {
+            let capacity = min(size, data.len().saturating_mul(MAX_EXPANSION));
+            let mut decompressed = Vec::with_capacity(capacity);
+            ZlibDecoder::new(data)
+                .read_to_end(&mut decompressed)
+                .context("Failed to inflate MDict block")?;
+            decompressed
+        }
+        kind => return Err(anyhow!("Unsupported MDict compression type {}", kind)),
+    };
+    if adler32(&decompressed).to_be_bytes() != head[4..8] {
+        return Err(anyhow!("Broken MDict block, the checksum doesn't match"));
+    }
+    Ok(decompressed)
+}
+
+/// A record block, with its place in the file and in the records.
+struct RecordBlock {
+    file_offset: u64,
+    compressed_size: usize,
+    /// The offset of its first record in all the decompressed records.
+    offset: usize,
+    size: usize,
+}
+
+/// The keys and the record blocks of an MDict file.
+struct Data {
+    /// The keys with the offsets of their records, in the file order.
+    keys: Vec<(String, usize)>,
+    /// The positions of the keys sorted by [ascii_casecmp], to find them.
+    sorted: Vec<usize>,
+    blocks: Vec<RecordBlock>,
+    /// The size of all the decompressed records.
+    size: usize,
+}
+
+/// An mdx or mdd file, of which only the header is read when opening it.
+struct MdictFile {
+    path: PathBuf,
+    file: RefCell<File>,
+    /// The size of the file, which no section read from it can exceed.
+    len: u64,
+    attributes: HashMap<String, String>,
+    /// The keys are in UTF-16 in mdd files and in the encoding of the header in mdx files.
+    encoding: &'static Encoding,
+    /// Numbers are 8 bytes wide from version 2.0, 4 bytes before.
+    width: usize,
+    /// The number of the keys given in the key section.
+    entries: usize,
+    /// The file offset of the key section.
+    key_section: u64,
+    data: OnceCell<Data>,
+    /// The last record block decompressed, with its position.
+    cache: RefCell<Option<(usize, Rc<Vec<u8>>)>>,
+}
+
+impl MdictFile {
+    /// Open an mdx or mdd file, reading its header and the size of its key section.
+    fn open(path: PathBuf) -> Result<MdictFile> {
+        let mut file =
+            File::open(&path).with_context(|| format!("Failed to open MDict file {:?}", path))?;
+        let broken = || anyhow!("Broken MDict header in {:?}", path);
+        let file_len = file.metadata()?.len();
+
+        let mut len = [0; 4];
+        file.read_exact(&mut len).map_err(|_| broken())?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len as u64 + 8 > file_len {
+            return Err(broken());
+        }
+        let mut header = vec![0; len + 4];
+        file.read_exact(&mut header).map_err(|_| broken())?;
+        if adler32(&header[..len]).to_le_bytes() != header[len..] {
+            return Err(broken());
+        }
+        let text = UTF_16LE.decode_without_bom_handling(&header[..len]).0;
+        let attribute = regex::Regex::new(r#"(\w+)="([^"]*)""#).expect("valid regex");
+        let attributes: HashMap<_, _> = attribute
+            .captures_iter(&text)
+            .map(|c| (c[1].to_owned(), unescape(&c[2])))
+            .collect();
+        let attr = |key: &str| attributes.get(key).map_or("", |v| v.trim());
+
+        if !matches!(attr("Encrypted"), "" | "0" | "No") {
+            return Err(anyhow!("Encrypted MDict file {:?} is not supported", path));
+        }
+        let version: f32 = attr("GeneratedByEngineVersion").parse().unwrap_or(2.0);
+        if version >= 3.0 {
+            return Err(anyhow!(
+                "MDict version {} of {:?} is not supported",
+                version,
+                path
+            ));
+        }
+        let is_mdd = path.extension().is_some_and(|ext| ext == "mdd");
+        let encoding = match attr("Encoding").to_ascii_uppercase().as_str() {
+            _ if is_mdd => UTF_16LE,
+            "" | "UTF-8" => UTF_8,
+            "UTF-16" => UTF_16LE,
+            "GBK" | "GB2312" => GB18030,
+            label => Encoding::for_label(label.as_bytes())
+                .with_context(|| format!("Unknown encoding {:?} of {:?}", label, path))?,
+        };
+        let width = if version >= 2.0 { 8 } else { 4 };
+
+        // The number of the keys is the second number of the key section.
+        let key_section = 4 + header.len() as u64;
+        let mut numbers = vec![0; width * 2];
+        file.read_exact(&mut numbers).map_err(|_| broken())?;
+        let entries = Bytes::new(&numbers[width..]).number(width)?;
+
+        Ok(MdictFile {
+            path,
+            file: RefCell::new(file),
+            len: file_len,
+            attributes,
+            encoding,
+            width,
+            entries,
+            key_section,
+            data: OnceCell::new(),
+            cache: RefCell::new(None),
+        })
+    }
+
+    fn attribute(&self, key: &str) -> &str {
+        self.attributes.get(key).map_or("", |v| v.trim())
+    }
+
+    /// Read `len` bytes at `offset` in the file.
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if offset.saturating_add(len as u64) > self.len {
+            return Err(anyhow!("Truncated MDict file {:?}", self.path));
+        }
+        let mut file = self.file.borrow_mut();
+        let mut buf = vec![0; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)
+            .with_context(|| format!("Truncated MDict file {:?}", self.path))?;
+        Ok(buf)
+    }
+
+    /// Decode a key or a text record.
+    fn decode(&self, bytes: &[u8]) -> String {
+        self.encoding
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned()
+    }
+
+    /// Get the keys and the record blocks, reading them on first use.
+    fn data(&self) -> Result<&Data> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = self
+            .read_data()
+            .with_context(|| format!("Failed to read MDict file {:?}", self.path))?;
+        Ok(self.data.get_or_init(|| data))
+    }
+
+    fn read_data(&self) -> Result<Data> {
+        let width = self.width;
+        let v2 = width == 8;
+        // The sizes of the first and the last keys of a block are in characters, with a
+        // null terminator from version 2.0.
+        let unit = if self.encoding == UTF_16LE { 2 } else { 1 };
+
+        let header_len = if v2 { width * 5 + 4 } else { width * 4 };
+        let header = self.read_at(self.key_section, header_len)?;
+        if v2 && adler32(&header[..width * 5]).to_be_bytes() != header[width * 5..] {
+            return Err(anyhow!("Broken key section header"));
+        }
+        let mut header = Bytes::new(&header);
+        let blocks = header.number(width)?;
+        header.number(width)?;
+        let info_size = if v2 {
+            Some(header.number(width)?)
+        } else {
+            None
+        };
+        let info_len = header.number(width)?;
+        let blocks_len = header.number(width)?;
+
+        let mut offset = self.key_section + header_len as u64;
+        let info = self.read_at(offset, info_len)?;
+        let info = match info_size {
+            Some(size) => decompress_block(&info, size)?,
+            None => info,
+        };
+        offset += info_len as u64;
+        let mut info = Bytes::new(&info);
+        let key_blocks = self.read_at(offset, blocks_len)?;
+        offset += blocks_len as u64;
+
+        let mut keys = Vec::with_capacity(min(self.entries, key_blocks.len()));
+        let mut key_blocks = Bytes::new(&key_blocks);
+        for _ in 0..blocks {
+            info.number(width)?;
+            for _ in 0..2 {
+                let len = if v2 {
+                    (info.number(2)? + 1) * unit
+                } else {
+                    info.number(1)? * unit
+                };
+                info.take(len)?;
+            }
+            let compressed_size = info.number(width)?;
+            let size = info.number(width)?;
+            let block = decompress_block(key_blocks.take(compressed_size)?, size)?;
+            let mut block = Bytes::new(&block);
+            while !block.is_empty() {
+                let record = block.number(width)?;
+                let key = self.decode(block.null_terminated(unit)?);
+                if keys.last().is_some_and(|&(_, last)| record < last) {
+                    return Err(anyhow!("The record of {:?} goes backwards", key));
+                }
+                keys.push((key, record));
+            }
+        }
+
+        let header = self.read_at(offset, width * 4)?;
+        offset += header.len() as u64;
+        let mut header = Bytes::new(&header);
+        let count = header.number(width)?;
+        header.number(width)?;
+        let info_len = header.number(width)?;
+        let blocks_len = header.number(width)?;
+        let info = self.read_at(offset, info_len)?;
+        offset += info_len as u64;
+        let mut info = Bytes::new(&info);
+        let mut blocks = Vec::with_capacity(min(count, info_len / (width * 2)));
+        let mut size = 0usize;
+        for _ in 0..count {
+            let block = RecordBlock {
+                file_offset: offset,
+                compressed_size: info.number(width)?,
+                offset: size,
+                size: info.number(width)?,
+            };
+            offset = offset
+                .checked_add(block.compressed_size as u64)
+                .ok_or_else(|| anyhow!("The record blocks are too large"))?;
+            size = size
+                .checked_add(block.size)
+                .ok_or_else(|| anyhow!("The record blocks are too large"))?;
+            blocks.push(block);
+        }
+        let compressed = blocks
+            .iter()
+            .try_fold(0usize, |sum, b| sum.checked_add(b.compressed_size))
+            .ok_or_else(|| anyhow!("The record blocks are too large"))?;
+        if compressed != blocks_len {
+            return Err(anyhow!(
+                "The record blocks take {} bytes, but {} in the record section header",
+                compressed,
+                blocks_len
+            ));
+        }
+        if let Some((key, _)) = keys.last().filter(|(_, record)| *record > size) {
+            return Err(anyhow!("The record of {:?} is out of range", key));
+        }
+        let mut sorted: Vec<_> = (0..keys.len()).collect();
+        sorted.sort_by(|&a, &b| ascii_casecmp(&keys[a].0, &keys[b].0));
+        Ok(Data {
+            keys,
+            sorted,
+            blocks,
+            size,
+        })
+    }
+
+    /// Get the decompressed `index`-th record block, keeping the last one in memory.
+    fn record_block(&self, index: usize) -> Result<Rc<Vec<u8>>> {
+        if let Some((i, block)) = &*self.cache.borrow() {
+            if *i == index {
+                return Ok(block.clone());
+            }
+        }
+        let info = &self.data()?.blocks[index];
+        let compressed = self.read_at(info.file_offset, info.compressed_size)?;
+        let block = decompress_block(&compressed, info.size)?;
+        if block.len() != info.size {
+            return Err(anyhow!(
+                "The record block {} has {} bytes, but {} in its header",
+                index,
+                block.len(),
+                info.size
+            ));
+        }
+        let block = Rc::new(block);
+        *self.cache.borrow_mut() = Some((index, block.clone()));
+        Ok(block)
+    }
+
+    /// Get the range of the record of the `index`-th key, which ends where the record of
+    /// the next key starts.
+    fn record_range(&self, index: usize) -> Result<(usize, usize)> {
+        let Data { keys, size, .. } = self.data()?;
+        let start = keys[index].1;
+        let end = keys.get(index + 1).map_or(*size, |k| k.1);
+        if start > end || end > *size {
+            return Err(anyhow!("The record of {:?} is out of range", keys[index].0));
+        }
+        Ok((start, end))
+    }
+
+    /// Read the record of the `index`-th key.
+    fn record(&self, index: usize) -> Result<Vec<u8>> {
+        let (start, end) = self.record_range(index)?;
+        let blocks = &self.data()?.blocks;
+        let mut record = Vec::with_capacity(end - start);
+        let mut pos = start;
+        while pos < end {
+            let i = blocks.partition_point(|b| b.offset + b.size <= pos);
+            let info = &blocks[i];
+            let block = self.record_block(i)?;
+            let to = min(end, info.offset + info.size);
+            record.extend_from_slice(&block[pos - info.offset..to - info.offset]);
+            pos = to;
+        }
+        Ok(record)
+    }
+
+    /// Find the keys equal to `word`, ignoring the case of ASCII letters, in the file order.
+    fn find(&self, word: &str) -> Result<Vec<usize>> {
+        let Data { keys, sorted, .. } = self.data()?;
+        let start = sorted.partition_point(|&i| ascii_casecmp(&keys[i].0, word).is_lt());
+        let end = sorted.partition_point(|&i| ascii_casecmp(&keys[i].0, word).is_le());
+        Ok(sorted[start..end].to_vec())
+    }
+}
+
+/// Replace the XML entities of a header attribute.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Get the MDict key of a resource path such as `img/rust.png`: `\img\rust.png`.
+fn resource_key(path: &str) -> String {
+    format!(
+        "\\{}",
+        path.trim_start_matches(['/', '\\']).replace('/', "\\")
+    )
+}
+
+/// An MDict dictionary.
+pub struct MDict {
+    mdx: MdictFile,
+    mdd_files: Vec<PathBuf>,
+    /// The mdd files, opened on first use.
+    mdds: OnceCell<Vec<MdictFile>>,
+    /// The problems tolerated while opening the dictionary, such as skipped mdx files.
+    warnings: Vec<String>,
+    fuzzy_index: OnceCell<BkTree>,
+    fulltext_index: OnceCell<FullTextIndex>,
+}
+
+/// Find the files in a directory with this extension, sorted.
+fn find_files(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<_> = dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Is the directory an MDict dictionary?
+pub fn is_mdict(dir: &Path) -> bool {
+    !find_files(dir, "mdx").is_empty()
+}
+
+impl MDict {
+    /// Open the MDict dictionary in a directory, reading the header of its mdx file only.
+    pub fn new(path: &Path) -> Result<MDict> {
+        let mut mdxs = find_files(path, "mdx").into_iter();
+        let mdx = mdxs
+            .next()
+            .with_context(|| format!("Missing .mdx file in {:?}", path))?;
+        // A dictionary is a directory, so the other mdx files need their own directories.
+        let warnings = mdxs
+            .map(|other| {
+                format!(
+                    "Skipping {:?}, only {:?} is used in its directory",
+                    other, mdx
+                )
+            })
+            .collect();
+        Ok(MDict {
+            mdx: MdictFile::open(mdx)?,
+            warnings,
+            mdd_files: find_files(path, "mdd"),
+            mdds: OnceCell::new(),
+            fuzzy_index: OnceCell::new(),
+            fulltext_index: OnceCell::new(),
+        })
+    }
+
+    /// Get the mdd files, reading their headers on first use.
+    fn mdds(&self) -> Result<&[MdictFile]> {
+        if let Some(mdds) = self.mdds.get() {
+            return Ok(mdds);
+        }
+        let mdds = self
+            .mdd_files
+            .iter()
+            .cloned()
+            .map(MdictFile::open)
+            .collect::<Result<_>>()?;
+        Ok(self.mdds.get_or_init(|| mdds))
+    }
+
+    /// Build the entry of the `index`-th key, following the redirections to other keys.
+    fn entry(&self, index: usize) -> Result<Entry<'_>> {
+        let keys = &self.mdx.data()?.keys;
+        let mut target = index;
+        let mut trans = String::new();
+        for _ in 0..MAX_LINKS {
+            let record = self.mdx.record(target)?;
+            trans = self.mdx.decode(&record);
+            trans.truncate(trans.trim_end_matches(['\0', '\r', '\n']).len());
+            let Some(link) = trans.strip_prefix(LINK) else {
+                break;
+            };
+            match self.mdx.find(link.trim())?.first() {
+                Some(&i) => target = i,
+                None => break,
+            }
+        }
+        Ok(Entry {
+            word: &keys[target].0,
+            parts: vec![EntryPart::Html(trans.clone())],
+            trans,
+            synonym: (target != index).then_some(keys[index].0.as_str()),
+        })
+    }
+
+    /// Get the title of the dictionary, or the name of the mdx file without one.
+    pub fn dict_name(&self) -> &str {
+        match self.mdx.attribute("Title") {
+            "" | "Title (No HTML code allowed)" => self
+                .mdx
+                .path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default(),
+            title => title,
+        }
+    }
+
+    /// Get the directory of the dictionary.
+    pub fn path(&self) -> &Path {
+        self.mdx.path.parent().unwrap_or(&self.mdx.path)
+    }
+
+    /// Get the number of the keys, the redirections included.
+    pub fn wordcount(&self) -> usize {
+        self.mdx.entries
+    }
+
+    /// Get the version of the MDict format.
+    pub fn version(&self) -> &str {
+        self.mdx.attribute("GeneratedByEngineVersion")
+    }
+
+    /// Get the description, in HTML.
+    pub fn description(&self) -> &str {
+        self.mdx.attribute("Description")
+    }
+
+    /// Get the creation date.
+    pub fn date(&self) -> &str {
+        self.mdx.attribute("CreationDate")
+    }
+
+    /// Get the problems tolerated while opening the dictionary.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Get the encoding of the definitions.
+    pub fn encoding(&self) -> &'static str {
+        self.mdx.encoding.name()
+    }
+
+    /// Get the mdx and mdd files.
+    pub fn files(&self) -> Vec<&Path> {
+        let mdds = self.mdd_files.iter().map(PathBuf::as_path);
+        std::iter::once(self.mdx.path.as_path())
+            .chain(mdds)
+            .collect()
+    }
+
+    /// Look up all the entries of a word, ignoring the case of ASCII letters.
+    ///
+    /// The entries reached through a redirection have the word as synonym.
+    pub fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        self.mdx
+            .find(word)?
+            .into_iter()
+            .map(|i| self.entry(i))
+            .collect()
+    }
+
+    /// Find the headwords matching a glob or a regular expression, in the mdx order.
+    pub fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .mdx
+            .data()?
+            .keys
+            .iter()
+            .map(|(word, _)| word.as_str())
+            .filter(|word| pattern.is_match(word) && seen.insert(*word))
+            .collect())
+    }
+
+    /// Look up a word with fuzzy searching enabled, ordering the entries by `ranking`.
+    pub fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        let keys = &self.mdx.data()?.keys;
+        self.fuzzy_index()?
+            .search_ranked(word, ranking, history, |i| {
+                let (start, end) = self.mdx.record_range(i)?;
+                Ok((keys[i].0.as_str(), end - start, i))
+            })?
+            .into_iter()
+            .map(|i| self.entry(i))
+            .collect()
+    }
+
+    /// Get the fuzzy searching index, loading it from the cache or building it on first use.
+    fn fuzzy_index(&self) -> Result<&BkTree> {
+        let path = &self.mdx.path;
+        BkTree::cached(&self.fuzzy_index, path, &[path], || {
+            let keys = &self.mdx.data()?.keys;
+            Ok(keys.iter().enumerate().map(|(i, k)| (k.0.as_str(), i)))
+        })
+    }
+
+    /// Find the entries whose definition contains `phrase`, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// Returns at most [FULLTEXT_LIMIT](crate::stardict::FULLTEXT_LIMIT) entries in the mdx order.
+    pub fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        self.fulltext_index()?.search(phrase, |doc| {
+            // Redirections are found through the entry they lead to.
+            let entry = self.entry(doc)?;
+            Ok(Some(entry).filter(|entry| entry.synonym.is_none()))
+        })
+    }
+
+    /// Get the full-text index, loading it from the cache or building it on first use.
+    fn fulltext_index(&self) -> Result<&FullTextIndex> {
+        let path = &self.mdx.path;
+        FullTextIndex::cached(
+            &self.fulltext_index,
+            self.dict_name(),
+            path,
+            &[path],
+            || {
+                let docs = (0..self.mdx.data()?.keys.len()).filter_map(|i| {
+                    let record = self.mdx.record(i).ok()?;
+                    Some((i as u32, self.mdx.decode(&record)))
+                });
+                Ok(docs)
+            },
+        )
+    }
+
+    /// Read the resource with this path, such as `img/rust.png`, from the mdd files.
+    pub fn resource(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let key = resource_key(path);
+        for mdd in self.mdds()? {
+            if let Some(&i) = mdd.find(&key)?.first() {
+                return Ok(Some(mdd.record(i)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the paths of all the resources, sorted.
+    pub fn resource_keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for mdd in self.mdds()? {
+            let data = mdd.data()?;
+            keys.extend(
+                data.keys
+                    .iter()
+                    .map(|(key, _)| key.trim_start_matches('\\').replace('\\', "/")),
+            );
+        }
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Get the first sound an entry links to with `sound://`.
+    pub fn sound(&self, entry: &Entry) -> Result<Option<Vec<u8>>> {
+        for (start, _) in entry.trans.match_indices("sound://") {
+            let rest = &entry.trans[start + "sound://".len()..];
+            let end = rest.find(['"', '\'', ' ', '>']).unwrap_or(rest.len());
+            if let Some(sound) = self.resource(&rest[..end])? {
+                return Ok(Some(sound));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check that the keys and the record blocks of the mdx file can be read, and that the
+    /// headers of the mdd files can.
+    ///
+    /// See [MDict::verify] for a full check.
+    pub fn check(&self) -> Result<()> {
+        self.mdx.data()?;
+        self.mdds()?;
+        Ok(())
+    }
+
+    /// Check the keys and the records of the mdx and mdd files.
+    pub fn verify(&self) -> Report {
+        let mut problems = Vec::new();
+        let mdds = self
+            .mdds()
+            .map_err(|e| problems.push(Problem::new("mdd", format!("{:#}", e))))
+            .unwrap_or_default();
+        for file in std::iter::once(&self.mdx).chain(mdds) {
+            let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+            let data = match file.data() {
+                Ok(data) => data,
+                Err(e) => {
+                    problems.push(Problem::new("keys", format!("{}: {:#}", name, e)));
+                    continue;
+                }
+            };
+            if data.keys.len() != file.entries {
+                problems.push(Problem::new(
+                    "wordcount",
+                    format!(
+                        "{} has {} keys, but {} in its header",
+                        name,
+                        data.keys.len(),
+                        file.entries
+                    ),
+                ));
+            }
+            let broken: Vec<_> = (0..data.blocks.len())
+                .filter(|&i| file.record_block(i).is_err())
+                .collect();
+            if let Some(&first) = broken.first() {
+                problems.push(Problem::with_items(
+                    "record_block",
+                    format!(
+                        "{} record blocks of {} are broken, the first is {}",
+                        broken.len(),
+                        name,
+                        first
+                    ),
+                    broken,
+                ));
+            }
+        }
+        Report {
+            path: self.path().to_owned(),
+            bookname: Some(self.dict_name().to_owned()),
+            problems,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32, is_mdict, MDict};
+    use crate::cache::test::tempdir;
+    use crate::pattern::Pattern;
+    use anyhow::Result;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::fs::{copy, create_dir_all, read, write};
+    use std::io::Write;
+    use std::path::Path;
+
+    /// Build a block of this compression type, LZO data being all literals.
+    fn block(kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut block = vec![kind, 0, 0, 0];
+        block.extend(adler32(data).to_be_bytes());
+        match kind {
+            0 => block.extend(data),
+            1 => {
+                block.push(17 + data.len() as u8);
+                block.extend(data);
+                block.extend([0x11, 0, 0]);
+            }
+            _ => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                block.extend(encoder.finish().unwrap());
+            }
+        }
+        block
+    }
+
+    /// Write an MDict file of two key blocks and two record blocks, compressed with zlib
+    /// and LZO.
+    fn write_mdict(path: &Path, version: &str, entries: &[(&str, &[u8])]) {
+        let width = if version == "2.0" { 8 } else { 4 };
+        let utf16 = path.extension().is_some_and(|ext| ext == "mdd");
+        let unit = if utf16 { 2 } else { 1 };
+        let number = |n: usize| (n as u64).to_be_bytes()[8 - width..].to_vec();
+        let encode = |s: &str| -> Vec<u8> {
+            match utf16 {
+                true => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+                false => s.as_bytes().to_vec(),
+            }
+        };
+
+        let header = format!(
+            "<Dictionary GeneratedByEngineVersion=\"{}\" Encrypted=\"0\" Encoding=\"UTF-8\" \
+             Title=\"Test MDict\" Description=\"A &lt;b&gt;test&lt;/b&gt;\"/>\r\n\0",
+            version
+        );
+        let header = encode_utf16(&header);
+        let mut file = (header.len() as u32).to_be_bytes().to_vec();
+        file.extend(&header);
+        file.extend(adler32(&header).to_le_bytes());
+
+        let (mut info, mut key_blocks, mut record_info, mut record_blocks) =
+            (vec![], vec![], vec![], vec![]);
+        let mut offset = 0;
+        let half = entries.len() / 2;
+        for (chunk, kind) in [(&entries[..half], 2), (&entries[half..], 1)] {
+            let (mut keys, mut records) = (vec![], vec![]);
+            for (word, record) in chunk {
+                keys.extend(number(offset));
+                keys.extend(encode(word));
+                keys.extend(vec![0; unit]);
+                records.extend(*record);
+                offset += record.len();
+            }
+            info.extend(number(chunk.len()));
+            for word in [chunk[0].0, chunk[chunk.len() - 1].0] {
+                let len = word.chars().count();
+                if width == 8 {
+                    info.extend((len as u16).to_be_bytes());
+                    info.extend(encode(word));
+                    info.extend(vec![0; unit]);
+                } else {
+                    info.push(len as u8);
+                    info.extend(encode(word));
+                }
+            }
+            let key_block = block(2, &keys);
+            info.extend(number(key_block.len()));
+            info.extend(number(keys.len()));
+            key_blocks.extend(key_block);
+            let record_block = block(kind, &records);
+            record_info.extend(number(record_block.len()));
+            record_info.extend(number(records.len()));
+            record_blocks.extend(record_block);
+        }
+
+        // From version 2.0, the key block info is compressed and its size given first.
+        let mut key_header = number(2);
+        key_header.extend(number(entries.len()));
+        let info = if width == 8 {
+            key_header.extend(number(info.len()));
+            block(2, &info)
+        } else {
+            info
+        };
+        key_header.extend(number(info.len()));
+        key_header.extend(number(key_blocks.len()));
+        file.extend(&key_header);
+        if width == 8 {
+            file.extend(adler32(&key_header).to_be_bytes());
+        }
+        file.extend(info);
+        file.extend(key_blocks);
+
+        file.extend(number(2));
+        file.extend(number(entries.len()));
+        file.extend(number(record_info.len()));
+        file.extend(number(record_blocks.len()));
+        file.extend(record_info);
+        file.extend(record_blocks);
+        write(path, file).unwrap();
+    }
+
+    fn encode_utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn read_mdict() {
+        let root = tempdir();
+        let root = root.path();
+        for version in ["2.0", "1.2"] {
+            let dir = root.join(version);
+            create_dir_all(&dir).unwrap();
+            write_mdict(
+                &dir.join("test.mdx"),
+                version,
+                &[
+                    ("Colour", b"@@@LINK=color\r\n\0"),
+                    (
+                        "color",
+                        b"<b>color</b> <a href=\"sound://color.wav\">play</a>\0",
+                    ),
+                    ("rust", b"<i>n.</i> iron oxide\0"),
+                    ("rusty", b"<i>adj.</i> covered with rust\0"),
+                ],
+            );
+            write_mdict(
+                &dir.join("test.mdd"),
+                version,
+                &[("\\color.wav", b"RIFF"), ("\\img\\rust.png", b"PNG")],
+            );
+
+            assert!(is_mdict(&dir));
+            let d = MDict::new(&dir).unwrap();
+            assert!(d.mdds.get().is_none());
+            assert!(d.check().is_ok());
+            assert_eq!(d.dict_name(), "Test MDict");
+            assert_eq!(d.description(), "A <b>test</b>");
+            assert_eq!(d.wordcount(), 4);
+            assert!(d.verify().problems.is_empty());
+
+            let entries = d.exact_lookup_all("colour").unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].word, "color");
+            assert_eq!(entries[0].synonym, Some("Colour"));
+            assert!(entries[0].trans.starts_with("<b>color</b>"));
+            assert_eq!(d.sound(&entries[0]).unwrap().unwrap(), b"RIFF");
+            let entries = d.exact_lookup_all("Rust").unwrap();
+            assert_eq!(entries[0].trans, "<i>n.</i> iron oxide");
+
+            let pattern = Pattern::parse("rust*").unwrap().unwrap();
+            assert_eq!(d.pattern_search(&pattern).unwrap(), ["rust", "rusty"]);
+            assert_eq!(d.resource_keys().unwrap(), ["color.wav", "img/rust.png"]);
+            assert_eq!(d.resource("img/rust.png").unwrap().unwrap(), b"PNG");
+            assert!(d.resource("missing.png").unwrap().is_none());
+            assert!(d.warnings().is_empty());
+
+            copy(dir.join("test.mdx"), dir.join("z.mdx")).unwrap();
+            let d = MDict::new(&dir).unwrap();
+            assert!(d.warnings()[0].contains("z.mdx"));
+        }
+    }
+
+    #[test]
+    fn reject_broken_mdict() {
+        let dir = tempdir();
+        let mdx = dir.path().join("test.mdx");
+        write_mdict(
+            &mdx,
+            "2.0",
+            &[("color", b"red\0"), ("rust", b"iron oxide\0")],
+        );
+        let data = read(&mdx).unwrap();
+        let lookup = || -> Result<()> {
+            MDict::new(dir.path())?.exact_lookup_all("rust")?;
+            Ok(())
+        };
+        assert!(lookup().is_ok());
+
+        for len in [0, 3, 10, data.len() / 2, data.len() - 5] {
+            write(&mdx, &data[..len]).unwrap();
+            assert!(lookup().is_err(), "truncated at {}", len);
+        }
+        // A header claiming 4 GiB isn't allocated.
+        write(&mdx, [&[0xff; 4][..], &data[4..]].concat()).unwrap();
+        assert!(lookup().is_err());
+        let garbage: Vec<_> = (0..4096u32).map(|i| (i * 7919 % 251) as u8).collect();
+        write(&mdx, garbage).unwrap();
+        assert!(lookup().is_err());
+
+        // The record section header of two blocks comes right before their sizes.
+        let number = |at: usize| u64::from_be_bytes(data[at..at + 8].try_into().unwrap());
+        let table = (0..data.len() - 32)
+            .map(|at| at + 32)
+            .find(|&at| {
+                number(at - 32) == 2
+                    && number(at - 16) == 32
+                    && at + 32 + number(at - 8) as usize == data.len()
+            })
+            .unwrap();
+        for sizes in [
+            [u64::MAX, 1, 1, 1],
+            [1, u64::MAX, 1, 1],
+            [1 << 63, 1, 1 << 63, 1],
+        ] {
+            let mut broken = data.clone();
+            for (i, size) in sizes.iter().enumerate() {
+                broken[table + i * 8..table + i * 8 + 8].copy_from_slice(&size.to_be_bytes());
+            }
+            write(&mdx, broken).unwrap();
+            assert!(lookup().is_err(), "record sizes {:?}", sizes);
+        }
+
+        // A broken mdd doesn't keep the mdx from opening, but fails the check.
+        write(&mdx, &data).unwrap();
+        write(dir.path().join("test.mdd"), &data[..10]).unwrap();
+        let d = MDict::new(dir.path()).unwrap();
+        assert!(d.exact_lookup_all("rust").is_ok());
+        assert!(d.check().is_err());
+        assert!(d.resource("rust.png").is_err());
+        assert_eq!(d.verify().problems[0].kind, "mdd");
+    }
+}
@@ -0,0 +1,114 @@
+//! Headword patterns: shell-style wildcards and regular expressions.
+//!
+//! Wildcards ignore the case of ASCII letters like the StarDict collation, so the literal
+//! prefix of a glob can be searched in the sorted idx before scanning.
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// A pattern matched against whole headwords.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `*` matches any characters and `?` matches one character.
+    Glob(String),
+    /// A regular expression, searched anywhere in the headword unless anchored.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Parse a query word into a pattern.
+    ///
+    /// A word starting with `:` is a regular expression and a word containing `*` or `?`
+    /// is a glob. Returns `None` for a plain word.
+    pub fn parse(word: &str) -> Result<Option<Pattern>> {
+        if let Some(re) = word.strip_prefix(':') {
+            let re = Regex::new(re).with_context(|| format!("Invalid regex {:?}", re))?;
+            Ok(Some(Pattern::Regex(re)))
+        } else if word.contains(['*', '?']) {
+            Ok(Some(Pattern::Glob(word.to_owned())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Does the pattern match the word?
+    pub fn is_match(&self, word: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => glob_match(glob, word),
+            Pattern::Regex(re) => re.is_match(word),
+        }
+    }
+
+    /// Get the literal prefix every matching word starts with, ignoring ASCII case.
+    pub fn literal_prefix(&self) -> &str {
+        match self {
+            Pattern::Glob(glob) => &glob[..glob.find(['*', '?']).unwrap_or(glob.len())],
+            Pattern::Regex(_) => "",
+        }
+    }
+}
+
+/// Match a glob against a whole word, ignoring the case of ASCII letters.
+pub fn glob_match(glob: &str, word: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    let (mut g, mut w) = (0, 0);
+    // The position of the last `*` and of the word when it was reached, to backtrack to.
+    let mut star = None;
+    while w < word.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, w));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c.eq_ignore_ascii_case(&word[w]) => {
+                g += 1;
+                w += 1;
+            }
+            _ => match star {
+                Some((star_g, star_w)) => {
+                    // Let the `*` swallow one more character.
+                    g = star_g + 1;
+                    w = star_w + 1;
+                    star = Some((star_g, star_w + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, Pattern};
+
+    #[test]
+    fn match_globs() {
+        for (glob, word, expected) in [
+            ("col*", "colour", true),
+            ("Col*", "colour", true),
+            ("col*", "cool", false),
+            ("colo?r", "colour", true),
+            ("colo?r", "color", false),
+            ("*our", "colour", true),
+            ("c*l*r", "colour", true),
+            ("c*l*r", "colours", false),
+            ("*", "", true),
+            ("?", "中", true),
+            ("a*b*c", "abbbc", true),
+            ("a*b?c", "abc", false),
+        ] {
+            assert_eq!(glob_match(glob, word), expected, "{} {}", glob, word);
+        }
+    }
+
+    #[test]
+    fn parse_patterns() {
+        assert!(Pattern::parse("colour").unwrap().is_none());
+        let glob = Pattern::parse("col?ur*").unwrap().unwrap();
+        assert_eq!(glob.literal_prefix(), "col");
+        let re = Pattern::parse(":^colou?r$").unwrap().unwrap();
+        assert!(re.is_match("color") && !re.is_match("colors"));
+        assert!(Pattern::parse(":(").is_err());
+    }
+}
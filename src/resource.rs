@@ -0,0 +1,243 @@
+//! Resource storage of the stardicts: pictures, sounds and other files referred to by
+//! the entries, such as `img:pic/rust.png` in a resource part.
+//!
+//! The resources are either plain files in the `res` directory next to the ifo file, or
+//! packed into `res.rifo`, `res.ridx` or `res.ridx.gz` and `res.rdic` or `res.rdic.dz`,
+//! which are laid out like the ifo, idx and dict files.
+use crate::stardict::{Dict, Idx};
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::fs::{read, read_to_string, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+
+/// The resource storage of a stardict.
+pub struct Resources(Storage);
+
+enum Storage {
+    /// Plain files in the `res` directory.
+    Dir(PathBuf),
+    /// A packed database, with the offset and the size of each resource in the rdic file.
+    Packed {
+        index: BTreeMap<String, (usize, usize)>,
+        rdic: Dict,
+    },
+}
+
+/// The fields of the `res.rifo` file used to read the ridx file.
+struct Rifo {
+    filecount: usize,
+    ridxfilesize: usize,
+    ridxoffsetbits: usize,
+}
+
+impl Rifo {
+    fn new(path: &Path) -> Result<Rifo> {
+        let text =
+            read_to_string(path).with_context(|| format!("Failed to open rifo file {:?}", path))?;
+        let mut rifo = Rifo {
+            filecount: 0,
+            ridxfilesize: 0,
+            ridxoffsetbits: 32,
+        };
+        for line in text.lines() {
+            let Some((key, val)) = line.split_once('=') else {
+                continue;
+            };
+            let field = match key {
+                "filecount" => &mut rifo.filecount,
+                "ridxfilesize" => &mut rifo.ridxfilesize,
+                "ridxoffsetbits" => &mut rifo.ridxoffsetbits,
+                _ => continue,
+            };
+            *field = val
+                .trim()
+                .parse()
+                .with_context(|| format!("Failed to parse rifo file {:?}", path))?;
+        }
+        Ok(rifo)
+    }
+}
+
+/// Is the key a relative path which stays inside the resource storage?
+pub fn is_safe_key(key: &str) -> bool {
+    Path::new(key)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Find the first of the files in `dir` which exists.
+fn find_file(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+impl Resources {
+    /// Open the resource storage of the stardict in `dir`, preferring the packed database.
+    ///
+    /// Returns `None` if the stardict has no resources.
+    pub fn open(dir: &Path) -> Result<Option<Resources>> {
+        let rifo = dir.join("res.rifo");
+        if !rifo.is_file() {
+            let res = dir.join("res");
+            return Ok(res.is_dir().then_some(Resources(Storage::Dir(res))));
+        }
+
+        let rifo_path = rifo;
+        let rifo = Rifo::new(&rifo_path)?;
+        let ridx = find_file(dir, &["res.ridx", "res.ridx.gz"])
+            .with_context(|| format!("Missing res.ridx or res.ridx.gz file in {:?}", dir))?;
+        let rdic = find_file(dir, &["res.rdic", "res.rdic.dz"])
+            .with_context(|| format!("Missing res.rdic or res.rdic.dz file in {:?}", dir))?;
+
+        let f =
+            File::open(&ridx).with_context(|| format!("Failed to open ridx file {:?}", ridx))?;
+        let f: Box<dyn BufRead> = if ridx.extension().is_some_and(|ext| ext == "gz") {
+            Box::new(BufReader::new(GzDecoder::new(f)))
+        } else {
+            Box::new(BufReader::new(f))
+        };
+        let (items, size) = match rifo.ridxoffsetbits {
+            32 => Idx::read_bytes::<4, u32>(&ridx, f)?,
+            64 => Idx::read_bytes::<8, u64>(&ridx, f)?,
+            bits => {
+                return Err(anyhow!(
+                    "Invalid ridxoffsetbits={} in {:?}",
+                    bits,
+                    rifo_path
+                ))
+            }
+        };
+        if rifo.ridxfilesize != 0 && rifo.ridxfilesize != size {
+            return Err(anyhow!(
+                "The size of ridx file {:?} is {} bytes, but ridxfilesize={} in the rifo file",
+                ridx,
+                size,
+                rifo.ridxfilesize
+            ));
+        }
+        if rifo.filecount != 0 && rifo.filecount != items.len() {
+            return Err(anyhow!(
+                "The ridx file {:?} has {} items, but filecount={} in the rifo file",
+                ridx,
+                items.len(),
+                rifo.filecount
+            ));
+        }
+
+        let index = items
+            .into_iter()
+            .map(|(key, offset, size)| (String::from_utf8_lossy(&key).into_owned(), (offset, size)))
+            .collect();
+        Ok(Some(Resources(Storage::Packed {
+            index,
+            rdic: Dict::new(rdic)?,
+        })))
+    }
+
+    /// Read the resource with this key, a path relative to the storage with `/` separators.
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match &self.0 {
+            Storage::Dir(dir) => {
+                if !is_safe_key(key) {
+                    return Err(anyhow!("Invalid resource key {:?}", key));
+                }
+                let path = dir.join(key);
+                if !path.is_file() {
+                    return Ok(None);
+                }
+                Ok(Some(read(&path).with_context(|| {
+                    format!("Failed to read resource {:?}", path)
+                })?))
+            }
+            Storage::Packed { index, rdic } => match index.get(key) {
+                Some(&(offset, size)) => {
+                    Ok(Some(rdic.get(offset, size).with_context(|| {
+                        format!("Failed to read resource {:?}", key)
+                    })?))
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Get the keys of all the resources, sorted.
+    pub fn keys(&self) -> Result<Vec<String>> {
+        match &self.0 {
+            Storage::Dir(dir) => {
+                let mut keys = Vec::new();
+                list_files(dir, "", &mut keys)?;
+                keys.sort();
+                Ok(keys)
+            }
+            Storage::Packed { index, .. } => Ok(index.keys().cloned().collect()),
+        }
+    }
+}
+
+/// Collect the paths of the files under `dir`, relative to the resource directory.
+fn list_files(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<()> {
+    for entry in dir
+        .read_dir()
+        .with_context(|| format!("Failed to open directory {:?}", dir))?
+        .flatten()
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let key = format!("{}{}", prefix, name);
+        let path = entry.path();
+        if path.is_dir() {
+            list_files(&path, &format!("{}/", key), keys)?;
+        } else {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resources;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn read_resources() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        let dir = root.join("plain");
+        create_dir_all(dir.join("res/snd")).unwrap();
+        write(dir.join("res/snd/rust.wav"), b"RIFF").unwrap();
+        write(dir.join("res/rust.png"), b"PNG").unwrap();
+        let res = Resources::open(&dir).unwrap().unwrap();
+        assert_eq!(res.keys().unwrap(), ["rust.png", "snd/rust.wav"]);
+        assert_eq!(res.get("snd/rust.wav").unwrap().unwrap(), b"RIFF");
+        assert!(res.get("missing.png").unwrap().is_none());
+        assert!(res.get("../plain/res/rust.png").is_err());
+
+        let dir = root.join("packed");
+        create_dir_all(&dir).unwrap();
+        let mut ridx = Vec::new();
+        for (key, offset, size) in [("rust.png", 0u32, 3u32), ("snd/rust.wav", 3, 4)] {
+            ridx.extend(key.as_bytes());
+            ridx.push(0);
+            ridx.extend(offset.to_be_bytes());
+            ridx.extend(size.to_be_bytes());
+        }
+        let rifo = format!(
+            "StarDict's storage ifo file\nversion=3.0.0\nfilecount=2\nridxfilesize={}\n",
+            ridx.len()
+        );
+        write(dir.join("res.rifo"), rifo).unwrap();
+        write(dir.join("res.ridx"), ridx).unwrap();
+        write(dir.join("res.rdic"), b"PNGRIFF").unwrap();
+        let res = Resources::open(&dir).unwrap().unwrap();
+        assert_eq!(res.keys().unwrap(), ["rust.png", "snd/rust.wav"]);
+        assert_eq!(res.get("snd/rust.wav").unwrap().unwrap(), b"RIFF");
+        assert!(res.get("missing.png").unwrap().is_none());
+
+        assert!(Resources::open(root).unwrap().is_none());
+    }
+}
@@ -1,34 +1,239 @@
 //! Look up words form the offline stardicts.
+use crate::cache::{cache_path, file_stamp, IdxCache};
+use crate::collate::{ascii_casecmp, ascii_prefix_cmp, stardict_strcmp};
+use crate::dictzip::{self, DictZip};
+use crate::fulltext::FullTextIndex;
+use crate::fuzzy::{BkTree, Ranking};
+use crate::pattern::Pattern;
+use crate::resource::Resources;
 use anyhow::{anyhow, Context, Result};
 use eio::FromBytes;
 use flate2::read::GzDecoder;
-use std::cmp::min;
-use std::fmt::Debug;
-use std::fs::{read, File};
-use std::io::{prelude::*, BufReader};
-use std::path::PathBuf;
+use serde::Serialize;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::fs::{create_dir_all, metadata, read, write, File};
+use std::io::{prelude::*, BufReader, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 /// The stardict to be looked up.
+///
+/// Only the ifo file is read when opening the stardict, the other files are loaded
+/// when it is first searched.
 #[allow(unused)]
 pub struct StarDict {
     ifo: Ifo,
+    files: Files,
+    data: OnceCell<Data>,
+    fuzzy_index: OnceCell<BkTree>,
+    fulltext_index: OnceCell<FullTextIndex>,
+    resources: OnceCell<Option<Resources>>,
+}
+
+/// The paths of the files of a stardict.
+struct Files {
+    ifo: PathBuf,
+    idx: PathBuf,
+    dict: PathBuf,
+    syn: Option<PathBuf>,
+}
+
+/// The searchable contents of a stardict.
+struct Data {
     idx: Idx,
     dict: Dict,
+    syn: Option<Syn>,
 }
 
+/// The default maximum edit distance of fuzzy searching.
+pub const FUZZY_MAX_DISTANCE: usize = 2;
+
+/// The default maximum number of entries found by fuzzy searching.
+pub const FUZZY_LIMIT: usize = 20;
+
+/// The maximum number of entries found by full-text searching.
+pub const FULLTEXT_LIMIT: usize = 100;
+
 /// A word entry of the stardict.
 pub struct Entry<'a> {
     pub word: &'a str,
-    pub trans: &'a str,
+    /// The text of all the parts, one part per line.
+    pub trans: String,
+    /// The typed parts of the word data.
+    pub parts: Vec<EntryPart>,
+    /// The synonym through which the entry was found, if any.
+    pub synonym: Option<&'a str>,
+}
+
+/// A typed part of the word data, see `sametypesequence` in the StarDict format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryPart {
+    /// `m`: pure text meaning.
+    Meaning(String),
+    /// `l`: pure text meaning in the locale encoding.
+    Locale(String),
+    /// `g`: Pango text markup.
+    Pango(String),
+    /// `t`: English phonetic string.
+    Phonetic(String),
+    /// `x`: XDXF markup.
+    Xdxf(String),
+    /// `y`: Chinese YinBiao or Japanese KANA.
+    YinBiao(String),
+    /// `k`: KingSoft PowerWord's XML data.
+    PowerWord(String),
+    /// `w`: MediaWiki markup.
+    MediaWiki(String),
+    /// `h`: HTML.
+    Html(String),
+    /// `n`: WordNet data.
+    WordNet(String),
+    /// `r`: resource file list, one `type:key` per line.
+    Resource(String),
+    /// `W`: WAV sound.
+    Wav(Vec<u8>),
+    /// `P`: picture.
+    Picture(Vec<u8>),
+    /// `X`: reserved for experimental extensions.
+    Experimental(Vec<u8>),
+    /// A type not defined by the StarDict format.
+    Unknown(char, Vec<u8>),
+}
+
+impl Entry<'_> {
+    /// Get the type and the key of each resource the entry refers to, such as
+    /// `("snd", "rust.wav")` for `snd:rust.wav` in a resource part.
+    pub fn resources(&self) -> Vec<(&str, &str)> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                EntryPart::Resource(refs) => Some(refs.lines().filter_map(|r| r.split_once(':'))),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+impl EntryPart {
+    fn new(kind: u8, data: &[u8]) -> EntryPart {
+        let text = || String::from_utf8_lossy(data).into_owned();
+        match kind {
+            b'm' => EntryPart::Meaning(text()),
+            b'l' => EntryPart::Locale(text()),
+            b'g' => EntryPart::Pango(text()),
+            b't' => EntryPart::Phonetic(text()),
+            b'x' => EntryPart::Xdxf(text()),
+            b'y' => EntryPart::YinBiao(text()),
+            b'k' => EntryPart::PowerWord(text()),
+            b'w' => EntryPart::MediaWiki(text()),
+            b'h' => EntryPart::Html(text()),
+            b'n' => EntryPart::WordNet(text()),
+            b'r' => EntryPart::Resource(text()),
+            b'W' => EntryPart::Wav(data.to_vec()),
+            b'P' => EntryPart::Picture(data.to_vec()),
+            b'X' => EntryPart::Experimental(data.to_vec()),
+            _ => EntryPart::Unknown(kind as char, data.to_vec()),
+        }
+    }
+
+    /// Get the text of a textual part.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            EntryPart::Meaning(s)
+            | EntryPart::Locale(s)
+            | EntryPart::Pango(s)
+            | EntryPart::Phonetic(s)
+            | EntryPart::Xdxf(s)
+            | EntryPart::YinBiao(s)
+            | EntryPart::PowerWord(s)
+            | EntryPart::MediaWiki(s)
+            | EntryPart::Html(s)
+            | EntryPart::WordNet(s)
+            | EntryPart::Resource(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Split the word data into typed parts.
+    ///
+    /// Lower-case types are strings and upper-case types are binary data. Without
+    /// `sametypesequence` every part starts with its type byte; with it, the type bytes
+    /// are left out, and so are the terminator or the size of the last part.
+    pub(crate) fn parse(mut data: &[u8], sametypesequence: &str) -> Result<Vec<EntryPart>> {
+        let broken = || anyhow!("Broken word data of type sequence {:?}", sametypesequence);
+        let mut parts = Vec::new();
+
+        let mut next = |kind: u8, last: bool, data: &mut &[u8]| -> Result<()> {
+            let len = if last {
+                data.len()
+            } else if kind.is_ascii_uppercase() {
+                let size = data.get(..4).ok_or_else(broken)?;
+                *data = &data[4..];
+                u32::from_be_bytes(size.try_into().unwrap()) as usize
+            } else {
+                data.iter().position(|&b| b == 0).unwrap_or(data.len())
+            };
+            let part = data.get(..len).ok_or_else(broken)?;
+            parts.push(EntryPart::new(kind, part));
+            *data = &data[len..];
+            if !last && kind.is_ascii_lowercase() && !data.is_empty() {
+                *data = &data[1..];
+            }
+            Ok(())
+        };
+
+        if sametypesequence.is_empty() {
+            while let Some((&kind, rest)) = data.split_first() {
+                data = rest;
+                next(kind, false, &mut data)?;
+            }
+        } else {
+            let types = sametypesequence.as_bytes();
+            for (i, &kind) in types.iter().enumerate() {
+                next(kind, i + 1 == types.len(), &mut data)?;
+            }
+        }
+        Ok(parts)
+    }
+}
+
+impl fmt::Display for EntryPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryPart::Phonetic(s) => write!(f, "[{}]", s),
+            EntryPart::Wav(data) => write!(f, "<WAV sound, {} bytes>", data.len()),
+            EntryPart::Picture(data) => write!(f, "<picture, {} bytes>", data.len()),
+            EntryPart::Experimental(data) => write!(f, "<experimental data, {} bytes>", data.len()),
+            EntryPart::Unknown(kind, data) => {
+                write!(f, "<unknown data of type {:?}, {} bytes>", kind, data.len())
+            }
+            part => write!(f, "{}", part.text().unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.synonym {
+            Some(synonym) => write!(f, "{} -> {}\n{}", synonym, self.word, self.trans),
+            None => write!(f, "{}\n{}", self.word, self.trans),
+        }
+    }
 }
 
 #[allow(unused)]
 impl<'a> StarDict {
-    /// Load stardict from a directory.
+    /// Open a stardict in a directory, reading its ifo file only.
     pub fn new(path: PathBuf) -> Result<StarDict> {
         let mut ifo: Option<_> = None;
         let mut idx: Option<_> = None;
+        let mut idx_gz: Option<_> = None;
         let mut dict: Option<_> = None;
+        let mut dict_dz: Option<_> = None;
+        let mut syn: Option<_> = None;
 
         for path in path
             .read_dir()
@@ -36,98 +241,301 @@ impl<'a> StarDict {
             .flatten()
         {
             let path = path.path();
-            if let Some(extension) = path.extension() {
-                match extension.to_str().unwrap() {
-                    "ifo" => ifo = Some(path),
-                    "idx" => idx = Some(path),
-                    "dz" => dict = Some(path),
-                    _ => (),
-                }
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.ends_with(".ifo") {
+                ifo = Some(path);
+            } else if name.ends_with(".idx") {
+                idx = Some(path);
+            } else if name.ends_with(".idx.gz") {
+                idx_gz = Some(path);
+            } else if name.ends_with(".dict") {
+                dict = Some(path);
+            } else if name.ends_with(".dict.dz") {
+                dict_dz = Some(path);
+            } else if name.ends_with(".syn") {
+                syn = Some(path);
             }
         }
 
-        if ifo.is_none() || idx.is_none() || dict.is_none() {
-            return Err(anyhow!("Stardict file is incomplete in {:?}", path));
-        }
+        // Prefer the uncompressed files, which can be read without inflating.
+        let ifo = ifo.with_context(|| format!("Missing .ifo file in {:?}", path))?;
+        let idx = idx
+            .or(idx_gz)
+            .with_context(|| format!("Missing .idx or .idx.gz file in {:?}", path))?;
+        let dict = dict
+            .or(dict_dz)
+            .with_context(|| format!("Missing .dict or .dict.dz file in {:?}", path))?;
 
-        let ifo = Ifo::new(ifo.unwrap())?;
-        let mut idx = Idx::new(idx.unwrap(), ifo.version())?;
-        let dict = Dict::new(dict.unwrap())?;
+        Ok(StarDict {
+            ifo: Ifo::new(ifo.clone())?,
+            files: Files {
+                ifo,
+                idx,
+                dict,
+                syn,
+            },
+            data: OnceCell::new(),
+            fuzzy_index: OnceCell::new(),
+            fulltext_index: OnceCell::new(),
+            resources: OnceCell::new(),
+        })
+    }
 
-        idx.items
-            .retain(|(word, offset, size)| offset + size < dict.contents.len());
+    /// Get the contents of the stardict, loading them on first use.
+    fn data(&self) -> Result<&Data> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = Data::new(&self.files, &self.ifo)?;
+        Ok(self.data.get_or_init(|| data))
+    }
+
+    /// Build the entry of the `index`-th idx item.
+    fn entry(&'a self, index: usize, synonym: Option<&'a str>) -> Result<Entry<'a>> {
+        let Data { idx, dict, .. } = self.data()?;
+        let (word, offset, size) = idx.get(index);
+        let data = dict.get(offset, size)?;
+        let parts = EntryPart::parse(&data, &self.ifo.sametypesequence)
+            .with_context(|| format!("Failed to parse the data of {:?}", word))?;
+        let trans = parts
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(Entry {
+            word,
+            trans,
+            parts,
+            synonym,
+        })
+    }
 
-        Ok(StarDict { ifo, idx, dict })
+    /// Find the range of the `len` sorted words, got by `key`, that equal `word`.
+    fn equal_range<'s>(len: usize, key: impl Fn(usize) -> &'s str, word: &str) -> Range<usize> {
+        let start = partition_point(len, |i| stardict_strcmp(key(i), word).is_lt());
+        let end = partition_point(len, |i| stardict_strcmp(key(i), word).is_le());
+        start..end
     }
 
     /// Look up a word with fuzzy searching disabled.
-    pub fn exact_lookup(&self, word: &str) -> Option<Entry> {
-        if let Ok(pos) = self.idx.items.binary_search_by(|probe| {
-            probe
-                .0
-                .to_lowercase()
-                .cmp(&word.to_lowercase())
-                .then(probe.0.as_str().cmp(word))
-        }) {
-            let (word, offset, size) = &self.idx.items[pos];
-            let trans = self.dict.get(*offset, *size);
-            Some(Entry { word, trans })
-        } else {
-            None
+    ///
+    /// Synonyms are consulted if the word is not a headword.
+    pub fn exact_lookup(&self, word: &str) -> Result<Option<Entry<'_>>> {
+        Ok(self.exact_lookup_all(word)?.into_iter().next())
+    }
+
+    /// Look up all the entries of a word with fuzzy searching disabled.
+    ///
+    /// A headword may have several entries, e.g. homographs. The entries reached through
+    /// synonyms follow those of the headword.
+    pub fn exact_lookup_all(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        let Data { idx, syn, .. } = self.data()?;
+        let mut indexes: Vec<_> = Self::equal_range(idx.len(), |i| idx.word(i), word)
+            .map(|i| (i, None))
+            .collect();
+        if let Some(syn) = syn {
+            let range = Self::equal_range(syn.items.len(), |i| &syn.items[i].0, word);
+            for (synonym, index) in &syn.items[range] {
+                if indexes.iter().all(|(i, _)| i != index) {
+                    indexes.push((*index, Some(synonym.as_str())));
+                }
+            }
+        }
+        indexes
+            .into_iter()
+            .map(|(index, synonym)| self.entry(index, synonym))
+            .collect()
+    }
+
+    /// Find the headwords starting with `prefix`, ignoring the case of ASCII letters.
+    pub fn prefix_search(&self, prefix: &str) -> Result<Vec<&str>> {
+        let idx = &self.data()?.idx;
+        let start = partition_point(idx.len(), |i| ascii_prefix_cmp(idx.word(i), prefix).is_lt());
+        let end = partition_point(idx.len(), |i| ascii_prefix_cmp(idx.word(i), prefix).is_le());
+        let mut words: Vec<_> = (start..end).map(|i| idx.word(i)).collect();
+        words.dedup();
+        Ok(words)
+    }
+
+    /// Find the headwords matching a glob or a regular expression, in the idx order.
+    ///
+    /// Only the headwords starting with the literal prefix of the pattern are scanned.
+    pub fn pattern_search(&self, pattern: &Pattern) -> Result<Vec<&str>> {
+        let mut words = self.prefix_search(pattern.literal_prefix())?;
+        words.retain(|word| pattern.is_match(word));
+        Ok(words)
+    }
+
+    /// Look up a word with fuzzy searching enabled.
+    ///
+    /// Returns the nearest entries within [FUZZY_MAX_DISTANCE], at most [FUZZY_LIMIT] of them.
+    pub fn fuzzy_lookup(&self, word: &str) -> Result<Vec<Entry<'_>>> {
+        self.fuzzy_lookup_with(word, FUZZY_MAX_DISTANCE, FUZZY_LIMIT)
+    }
+
+    /// Look up a word with fuzzy searching enabled.
+    ///
+    /// Returns at most `limit` entries within `max_distance` edits of the word, the nearest
+    /// first. Both headwords and synonyms are searched.
+    pub fn fuzzy_lookup_with(
+        &self,
+        word: &str,
+        max_distance: usize,
+        limit: usize,
+    ) -> Result<Vec<Entry<'_>>> {
+        let Data { idx, syn, .. } = self.data()?;
+        self.fuzzy_index()?
+            .search(word, max_distance, limit)
+            .into_iter()
+            .map(|(_, value)| match value.checked_sub(idx.len()) {
+                None => self.entry(value, None),
+                Some(i) => {
+                    let (synonym, index) = &syn.as_ref().unwrap().items[i];
+                    self.entry(*index, Some(synonym))
+                }
+            })
+            .collect()
+    }
+
+    /// Look up a word with fuzzy searching enabled, ordering the entries by `ranking`.
+    ///
+    /// `history` holds the words already looked up, see [Ranking::rank].
+    pub fn fuzzy_lookup_ranked(
+        &self,
+        word: &str,
+        ranking: &Ranking,
+        history: &HashSet<String>,
+    ) -> Result<Vec<Entry<'_>>> {
+        let Data { idx, syn, .. } = self.data()?;
+        self.fuzzy_index()?
+            .search_ranked(word, ranking, history, |value| {
+                Ok(match value.checked_sub(idx.len()) {
+                    None => (idx.word(value), idx.get(value).2, (value, None)),
+                    Some(i) => {
+                        let (synonym, index) = &syn.as_ref().unwrap().items[i];
+                        (
+                            synonym.as_str(),
+                            idx.get(*index).2,
+                            (*index, Some(synonym.as_str())),
+                        )
+                    }
+                })
+            })?
+            .into_iter()
+            .map(|(index, synonym)| self.entry(index, synonym))
+            .collect()
+    }
+
+    /// Get the fuzzy searching index, loading it from the cache or building it on first use.
+    ///
+    /// The values of the headwords are their positions in the idx items, followed by
+    /// the synonyms.
+    fn fuzzy_index(&self) -> Result<&BkTree> {
+        BkTree::cached(&self.fuzzy_index, &self.files.ifo, &self.files(), || {
+            let Data { idx, syn, .. } = self.data()?;
+            let headwords = (0..idx.len()).map(|i| idx.word(i));
+            let synonyms = syn
+                .iter()
+                .flat_map(|syn| syn.items.iter().map(|item| item.0.as_str()));
+            Ok(headwords.chain(synonyms).enumerate().map(|(i, w)| (w, i)))
+        })
+    }
+
+    /// Find the entries whose definition contains `phrase`, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// Returns at most [FULLTEXT_LIMIT] entries in the idx order.
+    pub fn fulltext_search(&self, phrase: &str) -> Result<Vec<Entry<'_>>> {
+        self.fulltext_index()?
+            .search(phrase, |doc| self.entry(doc, None).map(Some))
+    }
+
+    /// Get the full-text index, loading it from the cache or building it on first use.
+    ///
+    /// The documents are the positions of the idx items.
+    fn fulltext_index(&self) -> Result<&FullTextIndex> {
+        let Files { ifo, idx, dict, .. } = &self.files;
+        let name = self.dict_name();
+        FullTextIndex::cached(&self.fulltext_index, name, ifo, &[ifo, idx, dict], || {
+            // A broken entry can't be found anyway.
+            let docs = (0..self.data()?.idx.len())
+                .filter_map(|i| Some((i as u32, self.entry(i, None).ok()?.trans)));
+            Ok(docs)
+        })
+    }
+
+    /// Check that the files of the stardict are complete without reading them: they must
+    /// exist and an uncompressed idx file must have the size given in the ifo file.
+    ///
+    /// See [StarDict::verify] for a full check.
+    pub fn check(&self) -> Result<()> {
+        for file in self.files() {
+            metadata(file).with_context(|| format!("Missing file {:?}", file))?;
+        }
+        let idx = &self.files.idx;
+        let size = metadata(idx)?.len() as usize;
+        if self.ifo.idxfilesize != 0
+            && idx.extension().is_none_or(|ext| ext != "gz")
+            && size != self.ifo.idxfilesize
+        {
+            return Err(anyhow!(
+                "The size of idx file {:?} is {} bytes, but idxfilesize={} in the ifo file",
+                idx,
+                size,
+                self.ifo.idxfilesize
+            ));
         }
+        Ok(())
     }
 
-    /// Calculate word distence for fuzzy searching.
-    fn min_edit_distance(pattern: &str, text: &str) -> usize {
-        let pattern_chars: Vec<_> = pattern.chars().collect();
-        let text_chars: Vec<_> = text.chars().collect();
-        let mut dist = vec![vec![0; pattern_chars.len() + 1]; text_chars.len() + 1];
+    /// Get the directory of the stardict.
+    pub fn path(&self) -> &Path {
+        self.files.ifo.parent().unwrap_or(&self.files.ifo)
+    }
 
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..=text_chars.len() {
-            dist[i][0] = i;
+    /// Get the resource storage, opening it on first use.
+    fn resources(&self) -> Result<Option<&Resources>> {
+        if let Some(resources) = self.resources.get() {
+            return Ok(resources.as_ref());
         }
+        let resources = Resources::open(self.path())?;
+        Ok(self.resources.get_or_init(|| resources).as_ref())
+    }
 
-        for j in 0..=pattern_chars.len() {
-            dist[0][j] = j;
+    /// Read the resource with this key, `None` if it is missing.
+    pub fn resource(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.resources()? {
+            Some(resources) => resources.get(key),
+            None => Ok(None),
         }
+    }
 
-        for i in 1..=text_chars.len() {
-            for j in 1..=pattern_chars.len() {
-                dist[i][j] = if text_chars[i - 1] == pattern_chars[j - 1] {
-                    dist[i - 1][j - 1]
-                } else {
-                    min(min(dist[i][j - 1], dist[i - 1][j]), dist[i - 1][j - 1]) + 1
-                }
-            }
+    /// Get the keys of all the resources, sorted.
+    pub fn resource_keys(&self) -> Result<Vec<String>> {
+        match self.resources()? {
+            Some(resources) => resources.keys(),
+            None => Ok(vec![]),
         }
-        dist[text_chars.len()][pattern_chars.len()]
     }
 
-    /// Look up a word with fuzzy searching enabled.
-    pub fn fuzzy_lookup(&self, word: &str) -> Option<Vec<Entry>> {
-        let distances: Vec<_> = self
-            .idx
-            .items
-            .iter()
-            .filter(|s| !s.0.is_empty())
-            .map(|s| Self::min_edit_distance(&word.to_lowercase(), &s.0.to_lowercase()))
-            .collect();
-        let min_dist = distances.iter().min()?;
-        let result = self
-            .idx
-            .items
-            .iter()
-            .filter(|s| !s.0.is_empty())
-            .enumerate()
-            .filter(|(idx, _)| distances[*idx] == *min_dist)
-            .map(|(_, x)| {
-                let (word, offset, size) = x;
-                let trans = self.dict.get(*offset, *size);
-                Entry { word, trans }
-            })
-            .collect::<Vec<_>>();
-        Some(result)
+    /// Get the sound of an entry: its embedded wav data, or the first sound resource
+    /// it refers to.
+    pub fn sound(&self, entry: &Entry) -> Result<Option<Vec<u8>>> {
+        for part in &entry.parts {
+            if let EntryPart::Wav(data) = part {
+                return Ok(Some(data.clone()));
+            }
+        }
+        for (kind, key) in entry.resources() {
+            if kind == "snd" {
+                if let Some(sound) = self.resource(key)? {
+                    return Ok(Some(sound));
+                }
+            }
+        }
+        Ok(None)
     }
 
     /// Get the name of the stardict.
@@ -139,12 +547,470 @@ impl<'a> StarDict {
     pub fn wordcount(&self) -> usize {
         self.ifo.wordcount
     }
+
+    /// Get the number of the synonyms in the syn file.
+    pub fn synwordcount(&self) -> usize {
+        self.ifo.synwordcount
+    }
+
+    /// Get the size of the idx file given in the ifo file.
+    pub fn idxfilesize(&self) -> usize {
+        self.ifo.idxfilesize
+    }
+
+    /// Get the width of the offsets in the idx file.
+    pub fn idx_offset_bits(&self) -> Result<usize> {
+        self.ifo.offset_bits()
+    }
+
+    /// Get the problems tolerated while reading the ifo file, such as an unknown version.
+    pub fn warnings(&self) -> &[String] {
+        &self.ifo.warnings
+    }
+
+    /// Get the version of the stardict format, `"unknown"` if unsupported.
+    pub fn version(&self) -> &'static str {
+        match self.ifo.version {
+            Version::V242 => "2.4.2",
+            Version::V300 => "3.0.0",
+            Version::Unknown => "unknown",
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        &self.ifo.author
+    }
+
+    pub fn email(&self) -> &str {
+        &self.ifo.email
+    }
+
+    pub fn website(&self) -> &str {
+        &self.ifo.website
+    }
+
+    /// Get the description, where `<br>` stands for a line break.
+    pub fn description(&self) -> &str {
+        &self.ifo.description
+    }
+
+    pub fn date(&self) -> &str {
+        &self.ifo.date
+    }
+
+    /// Get the types of the data parts shared by all the entries, if any.
+    pub fn sametypesequence(&self) -> &str {
+        &self.ifo.sametypesequence
+    }
+
+    pub fn dicttype(&self) -> &str {
+        &self.ifo.dicttype
+    }
+
+    /// Get the ifo, idx, dict and syn files.
+    pub fn files(&self) -> Vec<&Path> {
+        let Files {
+            ifo,
+            idx,
+            dict,
+            syn,
+        } = &self.files;
+        [ifo, idx, dict]
+            .into_iter()
+            .chain(syn)
+            .map(|p| p.as_path())
+            .collect()
+    }
+}
+
+/// A defect of a stardict found by [StarDict::verify].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    /// The kind of the defect, such as `wordcount` or `unsorted`.
+    pub kind: &'static str,
+    pub message: String,
+    /// The positions of the first [Problem::MAX_ITEMS] offending idx or syn items, if any.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<usize>,
+}
+
+impl Problem {
+    /// The most item positions kept in a problem, the message telling the total count.
+    pub const MAX_ITEMS: usize = 100;
+
+    pub fn new(kind: &'static str, message: String) -> Problem {
+        Problem {
+            kind,
+            message,
+            items: vec![],
+        }
+    }
+
+    /// A problem with the positions of the offending items, keeping only the first ones.
+    pub fn with_items(kind: &'static str, message: String, mut items: Vec<usize>) -> Problem {
+        items.truncate(Problem::MAX_ITEMS);
+        Problem {
+            kind,
+            message,
+            items,
+        }
+    }
+}
+
+/// The defects of a stardict, see [StarDict::verify].
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub path: PathBuf,
+    /// The bookname, unless the stardict couldn't be opened at all.
+    pub bookname: Option<String>,
+    pub problems: Vec<Problem>,
+}
+
+/// Collect the positions of the items with the same kind of defect into one problem.
+struct ItemProblems {
+    kind: &'static str,
+    /// Describe the defect from the number of items and the first one.
+    describe: fn(usize, &str) -> String,
+    items: Vec<usize>,
+    count: usize,
+    first: String,
+}
+
+impl ItemProblems {
+    fn new(kind: &'static str, describe: fn(usize, &str) -> String) -> ItemProblems {
+        ItemProblems {
+            kind,
+            describe,
+            items: vec![],
+            count: 0,
+            first: String::new(),
+        }
+    }
+
+    fn push(&mut self, item: usize, detail: impl FnOnce() -> String) {
+        if self.count == 0 {
+            self.first = detail();
+        }
+        if self.items.len() < Problem::MAX_ITEMS {
+            self.items.push(item);
+        }
+        self.count += 1;
+    }
+
+    fn report(self, problems: &mut Vec<Problem>) {
+        if self.count > 0 {
+            problems.push(Problem::with_items(
+                self.kind,
+                (self.describe)(self.count, &self.first),
+                self.items,
+            ));
+        }
+    }
+}
+
+impl StarDict {
+    /// Check the ifo, idx, dict and syn files against each other and against the
+    /// stardict format, reading them strictly instead of skipping the broken items.
+    pub fn verify(&self) -> Report {
+        let mut problems = Vec::new();
+        self.verify_files(&mut problems);
+        Report {
+            path: self.path().to_owned(),
+            bookname: Some(self.ifo.bookname.clone()),
+            problems,
+        }
+    }
+
+    fn verify_files(&self, problems: &mut Vec<Problem>) {
+        let ifo = &self.ifo;
+        let (items, idx_size) = match Idx::read_raw(&self.files.idx, ifo) {
+            Ok(read) => read,
+            Err(e) => return problems.push(Problem::new("idx", format!("{:#}", e))),
+        };
+
+        if ifo.wordcount != items.len() {
+            problems.push(Problem::new(
+                "wordcount",
+                format!(
+                    "wordcount={} in the ifo file, but the idx file has {} items",
+                    ifo.wordcount,
+                    items.len()
+                ),
+            ));
+        }
+        if ifo.idxfilesize != idx_size {
+            problems.push(Problem::new(
+                "idxfilesize",
+                format!(
+                    "idxfilesize={} in the ifo file, but the idx file has {} bytes",
+                    ifo.idxfilesize, idx_size
+                ),
+            ));
+        }
+
+        let mut invalid_utf8 = ItemProblems::new("invalid_utf8", |n, first| {
+            format!("{} headwords aren't valid UTF-8, first {}", n, first)
+        });
+        let mut empty = ItemProblems::new("empty_word", |n, first| {
+            format!("{} headwords are empty, first {}", n, first)
+        });
+        let mut unsorted = ItemProblems::new("unsorted", |n, first| {
+            format!("{} headwords are out of order, first {}", n, first)
+        });
+        let words: Vec<_> = items
+            .iter()
+            .map(|(word, _, _)| String::from_utf8_lossy(word))
+            .collect();
+        for (i, (word, _, _)) in items.iter().enumerate() {
+            if std::str::from_utf8(word).is_err() {
+                invalid_utf8.push(i, || format!("{:?} at item {}", words[i], i));
+            }
+            if word.is_empty() {
+                empty.push(i, || format!("at item {}", i));
+            }
+            if i > 0 && stardict_strcmp(&words[i - 1], &words[i]).is_gt() {
+                unsorted.push(i, || {
+                    format!("{:?} after {:?} at item {}", words[i], words[i - 1], i)
+                });
+            }
+        }
+        invalid_utf8.report(problems);
+        empty.report(problems);
+        unsorted.report(problems);
+
+        match Dict::new(self.files.dict.clone()) {
+            Ok(dict) => {
+                let len = dict.len();
+                let mut out_of_range = ItemProblems::new("out_of_range", |n, first| {
+                    format!("{} items point beyond the dict file, first {}", n, first)
+                });
+                for (i, (_, offset, size)) in items.iter().enumerate() {
+                    if offset.checked_add(*size).is_none_or(|end| end > len) {
+                        out_of_range.push(i, || {
+                            format!(
+                                "{:?} at item {} with offset {} and size {}, the dict has {} bytes",
+                                words[i], i, offset, size, len
+                            )
+                        });
+                    }
+                }
+                out_of_range.report(problems);
+            }
+            Err(e) => problems.push(Problem::new("dict", format!("{:#}", e))),
+        }
+
+        let Some(syn) = &self.files.syn else {
+            return;
+        };
+        let syn = match Syn::new(syn.clone()) {
+            Ok(syn) => syn,
+            Err(e) => return problems.push(Problem::new("syn", format!("{:#}", e))),
+        };
+        if ifo.synwordcount != syn.items.len() {
+            problems.push(Problem::new(
+                "synwordcount",
+                format!(
+                    "synwordcount={} in the ifo file, but the syn file has {} items",
+                    ifo.synwordcount,
+                    syn.items.len()
+                ),
+            ));
+        }
+        let mut dangling = ItemProblems::new("syn_target", |n, first| {
+            format!("{} synonyms point beyond the idx items, first {}", n, first)
+        });
+        for (i, (word, target)) in syn.items.iter().enumerate() {
+            if *target >= items.len() {
+                dangling.push(i, || format!("{:?} to item {}", word, target));
+            }
+        }
+        dangling.report(problems);
+    }
+}
+
+impl Data {
+    /// Load the idx, dict and syn files.
+    fn new(files: &Files, ifo: &Ifo) -> Result<Data> {
+        let dict = Dict::new(files.dict.clone())?;
+        let (idx, dropped) = Idx::new(files, ifo, &dict)?;
+        let mut syn = files.syn.clone().map(Syn::new).transpose()?;
+
+        if let Some(syn) = syn.as_mut() {
+            // Synonyms refer to the idx items by their position in the idx file, so move
+            // them past the dropped items, and drop those pointing beyond the last item.
+            syn.items
+                .retain_mut(|(_, i)| match dropped.binary_search(i) {
+                    Ok(_) => false,
+                    Err(before) => {
+                        *i -= before;
+                        *i < idx.len()
+                    }
+                });
+        }
+
+        Ok(Data { idx, dict, syn })
+    }
+}
+
+/// Builds a stardict of plain text definitions, the reverse of [StarDict::new].
+///
+/// The headwords are sorted as stardict expects, and the dict file is compressed
+/// with dictzip.
+#[derive(Debug, Clone, Default)]
+pub struct StarDictBuilder {
+    pub bookname: String,
+    pub author: String,
+    pub email: String,
+    pub website: String,
+    /// The description, line breaks are written as `<br>`.
+    pub description: String,
+    pub date: String,
+    entries: Vec<(String, String)>,
+    /// Synonyms with the headword they refer to.
+    synonyms: Vec<(String, String)>,
+}
+
+impl StarDictBuilder {
+    pub fn new(bookname: &str) -> StarDictBuilder {
+        StarDictBuilder {
+            bookname: bookname.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Add an entry, a headword may have several entries.
+    pub fn add(&mut self, word: &str, definition: &str) {
+        self.entries.push((word.to_owned(), definition.to_owned()));
+    }
+
+    /// Add a synonym of the headword `word`.
+    pub fn add_synonym(&mut self, synonym: &str, word: &str) {
+        self.synonyms.push((synonym.to_owned(), word.to_owned()));
+    }
+
+    /// Check that a headword or a synonym can be written to the idx or syn file.
+    fn check_word(word: &str) -> Result<()> {
+        if word.is_empty() || word.contains('\0') || word.len() >= 256 {
+            return Err(anyhow!(
+                "Invalid headword {:?}, it must have 1 to 255 bytes without NUL",
+                word
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the stardict files named `name` into the directory `dir`.
+    pub fn write(&self, dir: &Path, name: &str) -> Result<()> {
+        if self.entries.is_empty() {
+            return Err(anyhow!("No entries to write for {}", self.bookname));
+        }
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| stardict_strcmp(&a.0, &b.0));
+
+        let mut dict = Vec::new();
+        let mut items = Vec::with_capacity(entries.len());
+        for (word, definition) in &entries {
+            StarDictBuilder::check_word(word)?;
+            let size = u32::try_from(definition.len())
+                .with_context(|| format!("The definition of {:?} is too long", word))?;
+            items.push((word.as_str(), dict.len() as u64, size));
+            dict.extend(definition.as_bytes());
+        }
+        // Like stardict's own tools, only switch to 64-bit offsets when needed.
+        let offset_bits_64 = dict.len() > u32::MAX as usize;
+        let mut idx = Vec::new();
+        for (word, offset, size) in &items {
+            idx.extend(word.as_bytes());
+            idx.push(0);
+            if offset_bits_64 {
+                idx.extend(offset.to_be_bytes());
+            } else {
+                idx.extend((*offset as u32).to_be_bytes());
+            }
+            idx.extend(size.to_be_bytes());
+        }
+
+        let mut synonyms = Vec::with_capacity(self.synonyms.len());
+        for (synonym, word) in &self.synonyms {
+            StarDictBuilder::check_word(synonym)?;
+            let index = partition_point(items.len(), |i| stardict_strcmp(items[i].0, word).is_lt());
+            if items.get(index).is_none_or(|item| item.0 != word) {
+                return Err(anyhow!(
+                    "The synonym {:?} refers to a missing headword {:?}",
+                    synonym,
+                    word
+                ));
+            }
+            synonyms.push((synonym.as_str(), index as u32));
+        }
+        synonyms.sort_by(|a, b| stardict_strcmp(a.0, b.0));
+        synonyms.dedup();
+
+        let one_line = |s: &str| s.replace(['\r', '\n'], " ");
+        let mut ifo = format!(
+            "StarDict's dict ifo file\nversion={}\nbookname={}\nwordcount={}\n",
+            if offset_bits_64 {
+                "3.0.0\nidxoffsetbits=64"
+            } else {
+                "2.4.2"
+            },
+            one_line(&self.bookname),
+            items.len()
+        );
+        if !synonyms.is_empty() {
+            ifo.push_str(&format!("synwordcount={}\n", synonyms.len()));
+        }
+        ifo.push_str(&format!("idxfilesize={}\n", idx.len()));
+        for (key, value) in [
+            ("author", &self.author),
+            ("email", &self.email),
+            ("website", &self.website),
+            ("date", &self.date),
+        ] {
+            if !value.is_empty() {
+                ifo.push_str(&format!("{}={}\n", key, one_line(value)));
+            }
+        }
+        if !self.description.is_empty() {
+            let description = self.description.trim().replace("\r\n", "\n");
+            ifo.push_str(&format!(
+                "description={}\n",
+                description.replace('\n', "<br>")
+            ));
+        }
+        ifo.push_str("sametypesequence=m\n");
+
+        create_dir_all(dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+        let write_file = |ext: &str, data: &[u8]| {
+            let path = dir.join(format!("{}.{}", name, ext));
+            write(&path, data).with_context(|| format!("Failed to write {:?}", path))
+        };
+        write_file("ifo", ifo.as_bytes())?;
+        write_file("idx", &idx)?;
+        // Data too large for dictzip, which also needs 64-bit offsets, is left uncompressed.
+        if dict.len() <= dictzip::max_len(dictzip::CHUNK_LEN) {
+            write_file("dict.dz", &dictzip::compress(&dict, dictzip::CHUNK_LEN)?)?;
+        } else {
+            write_file("dict", &dict)?;
+        }
+        if !synonyms.is_empty() {
+            let mut syn = Vec::new();
+            for (synonym, index) in synonyms {
+                syn.extend(synonym.as_bytes());
+                syn.push(0);
+                syn.extend(index.to_be_bytes());
+            }
+            write_file("syn", &syn)?;
+        }
+        Ok(())
+    }
 }
 
 /// bookname=      // required
 /// wordcount=     // required
 /// synwordcount=  // required if ".syn" file exists.
 /// idxfilesize=   // required
+/// tdxfilesize=   // required by tree dictionaries instead of idxfilesize
 /// idxoffsetbits= // New in 3.0.0
 /// author=
 /// email=
@@ -153,23 +1019,26 @@ impl<'a> StarDict {
 /// date=
 /// sametypesequence= // very important.
 /// dicttype=
-
 #[allow(unused)]
 #[derive(Debug)]
-struct Ifo {
+pub(crate) struct Ifo {
     version: Version,
-    bookname: String,
-    wordcount: usize,
+    pub(crate) bookname: String,
+    pub(crate) wordcount: usize,
     synwordcount: usize,
     idxfilesize: usize,
+    /// The size of the tdx file of a tree dictionary.
+    pub(crate) tdxfilesize: usize,
     idxoffsetbits: usize,
     author: String,
     email: String,
     website: String,
     description: String,
     date: String,
-    sametypesequence: String,
+    pub(crate) sametypesequence: String,
     dicttype: String,
+    /// The problems tolerated while parsing, for the caller to report.
+    warnings: Vec<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -181,13 +1050,14 @@ enum Version {
 
 #[allow(unused)]
 impl Ifo {
-    fn new(path: PathBuf) -> Result<Ifo> {
+    pub(crate) fn new(path: PathBuf) -> Result<Ifo> {
         let mut ifo = Ifo {
             version: Version::Unknown,
             bookname: String::new(),
             wordcount: 0,
             synwordcount: 0,
             idxfilesize: 0,
+            tdxfilesize: 0,
             idxoffsetbits: 0,
             author: String::new(),
             email: String::new(),
@@ -196,6 +1066,7 @@ impl Ifo {
             date: String::new(),
             sametypesequence: String::new(),
             dicttype: String::new(),
+            warnings: Vec::new(),
         };
 
         for line in BufReader::new(
@@ -233,6 +1104,11 @@ impl Ifo {
                             .parse()
                             .with_context(|| format!("Failed to parse info file {:?}", path))?
                     }
+                    "tdxfilesize" => {
+                        ifo.tdxfilesize = val
+                            .parse()
+                            .with_context(|| format!("Failed to parse info file {:?}", path))?
+                    }
                     "idxoffsetbits" => {
                         ifo.idxoffsetbits = val
                             .parse()
@@ -249,54 +1125,172 @@ impl Ifo {
                 };
             }
         }
+
+        if let Version::Unknown = ifo.version {
+            ifo.warnings.push(format!(
+                "Unknown stardict version in {:?}, reading it as version 3.0.0",
+                path
+            ));
+        }
+
         Ok(ifo)
     }
 
     fn version(&self) -> Version {
         self.version
     }
+
+    /// Get the width of the offsets in the idx file.
+    ///
+    /// Only 3.0.0 dictionaries with `idxoffsetbits=64` use 64-bit offsets.
+    fn offset_bits(&self) -> Result<usize> {
+        match (self.version, self.idxoffsetbits) {
+            (Version::V242, _) => Ok(32),
+            (_, 0 | 32) => Ok(32),
+            (_, 64) => Ok(64),
+            (_, bits) => Err(anyhow!(
+                "Invalid idxoffsetbits={} in stardict {}",
+                bits,
+                self.bookname
+            )),
+        }
+    }
 }
 
-#[allow(unused)]
-struct Dict {
-    contents: String,
+/// The contents of the `.dict` or `.dict.dz` file.
+pub(crate) enum Dict {
+    /// An uncompressed `.dict` file, read on demand.
+    Plain(RefCell<File>, usize),
+    /// Inflated only chunk by chunk on demand.
+    DictZip(DictZip),
+    /// A plain gzip file without a chunk table, inflated as a whole.
+    Inflated(Vec<u8>),
 }
 
-#[allow(unused)]
-impl<'a> Dict {
-    fn new(path: PathBuf) -> Result<Dict> {
+impl Dict {
+    pub(crate) fn new(path: PathBuf) -> Result<Dict> {
+        if path.extension().is_none_or(|ext| ext != "dz") {
+            let f = File::open(&path)
+                .with_context(|| format!("Failed to open dict file {:?}", path))?;
+            let len = f.metadata()?.len() as usize;
+            return Ok(Dict::Plain(RefCell::new(f), len));
+        }
+        if let Some(dz) = DictZip::open(&path)? {
+            return Ok(Dict::DictZip(dz));
+        }
         let s =
             read(&path).with_context(|| format!("Failed to open stardict directory {:?}", path))?;
         let mut d = GzDecoder::new(s.as_slice());
-        let mut contents = String::new();
-        d.read_to_string(&mut contents).with_context(|| {
+        let mut contents = Vec::new();
+        d.read_to_end(&mut contents).with_context(|| {
             format!("Failed to open stardict directory {:?} as dz format", path)
         })?;
-        Ok(Dict { contents })
+        Ok(Dict::Inflated(contents))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Dict::Plain(_, len) => *len,
+            Dict::DictZip(dz) => dz.len(),
+            Dict::Inflated(contents) => contents.len(),
+        }
     }
 
-    fn get(&'a self, offset: usize, size: usize) -> &'a str {
-        &self.contents[offset..offset + size]
+    pub(crate) fn get(&self, offset: usize, size: usize) -> Result<Vec<u8>> {
+        if offset.checked_add(size).is_none_or(|end| end > self.len()) {
+            return Err(anyhow!(
+                "{} bytes at offset {} are out of the dict data of length {}",
+                size,
+                offset,
+                self.len()
+            ));
+        }
+        Ok(match self {
+            Dict::Plain(f, _) => {
+                let mut f = f.borrow_mut();
+                let mut bytes = vec![0; size];
+                f.seek(SeekFrom::Start(offset as u64))?;
+                f.read_exact(&mut bytes)?;
+                bytes
+            }
+            Dict::DictZip(dz) => dz.read(offset, size)?,
+            Dict::Inflated(contents) => contents[offset..offset + size].to_vec(),
+        })
     }
 }
 
-#[allow(unused)]
-#[derive(Debug)]
-struct Idx {
-    items: Vec<(String, usize, usize)>,
+/// A headword with the offset and the size of its data in the dict file.
+pub(crate) type IdxItem = (String, usize, usize);
+
+/// An idx item as read, its headword may not be valid UTF-8.
+pub(crate) type RawIdxItem = (Vec<u8>, usize, usize);
+
+/// The idx items, parsed from the idx file or mapped from the cache.
+pub(crate) enum Idx {
+    Parsed(Vec<IdxItem>),
+    Cached(IdxCache),
+}
+
+/// Find the first of `len` positions where `pred` turns false, like `slice::partition_point`.
+pub(crate) fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(mid) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
 }
 
-#[allow(unused)]
 impl Idx {
-    fn read_bytes<const N: usize, T>(path: PathBuf) -> Result<Vec<(String, usize, usize)>>
+    /// Sort the items by their headwords ignoring the case of ASCII letters, keeping the
+    /// order of the items with the same headword, for [Idx::folded_range].
+    pub(crate) fn sort_folded(items: &mut [IdxItem]) {
+        items.sort_by(|a, b| ascii_casecmp(&a.0, &b.0));
+    }
+
+    /// Load the items of the index in `file` from the cache, or get them from `parse`, sort
+    /// them by [Idx::sort_folded] and cache them.
+    pub(crate) fn cached_folded(
+        file: &Path,
+        parse: impl FnOnce() -> Result<Vec<IdxItem>>,
+    ) -> Result<Idx> {
+        let stamp = file_stamp(&[file])?;
+        let cache = cache_path(file, "idx");
+        if let Some(cached) = cache.as_deref().and_then(|p| IdxCache::load(p, &stamp)) {
+            return Ok(Idx::Cached(cached));
+        }
+        let mut items = parse()?;
+        Idx::sort_folded(&mut items);
+        if let Some(cache) = cache {
+            // The cache only makes the next start faster, so a failure to write is ignored.
+            let _ = IdxCache::write(&cache, &stamp, &items, &[]);
+        }
+        Ok(Idx::Parsed(items))
+    }
+
+    /// Get the positions of the items whose headword is `word` ignoring the case of ASCII
+    /// letters, the items being sorted by [Idx::sort_folded].
+    pub(crate) fn folded_range(&self, word: &str) -> Range<usize> {
+        let start = partition_point(self.len(), |i| ascii_casecmp(self.get(i).0, word).is_lt());
+        let end = partition_point(self.len(), |i| ascii_casecmp(self.get(i).0, word).is_le());
+        start..end
+    }
+
+    /// Read the idx items, with the headwords as raw bytes, and the total number of bytes read.
+    pub(crate) fn read_bytes<const N: usize, T>(
+        path: &Path,
+        mut f: impl BufRead,
+    ) -> Result<(Vec<RawIdxItem>, usize)>
     where
         T: FromBytes<N> + TryInto<usize>,
         <T as TryInto<usize>>::Error: Debug,
     {
-        let f = File::open(&path).with_context(|| format!("Failed to open idx file {:?}", path))?;
-        let mut f = BufReader::new(f);
-
         let mut items: Vec<_> = Vec::new();
+        let mut total = 0;
 
         loop {
             let mut buf: Vec<u8> = Vec::new();
@@ -308,6 +1302,7 @@ impl Idx {
             if read_bytes == 0 {
                 break;
             }
+            total += read_bytes + N + 4;
 
             if let Some(&trailing) = buf.last() {
                 if trailing == b'\0' {
@@ -315,46 +1310,240 @@ impl Idx {
                 }
             }
 
-            let mut word: String = String::from_utf8_lossy(&buf)
-                .chars()
-                .filter(|&c| c != '\u{fffd}')
-                .collect();
-
             let mut b = [0; N];
-            f.read(&mut b)
+            f.read_exact(&mut b)
                 .with_context(|| format!("Failed to parse idx file {:?}", path))?;
             let offset = T::from_be_bytes(b).try_into().unwrap();
 
-            let mut b = [0; N];
-            f.read(&mut b)
+            // The size is 32-bit whatever the width of the offset is.
+            let mut b = [0; 4];
+            f.read_exact(&mut b)
                 .with_context(|| format!("Failed to parse idx file {:?}", path))?;
-            let size = T::from_be_bytes(b).try_into().unwrap();
+            let size = u32::from_be_bytes(b) as usize;
 
-            if !word.is_empty() {
-                items.push((word, offset, size))
+            items.push((buf, offset, size))
+        }
+        Ok((items, total))
+    }
+
+    /// Load the valid idx items, from the cache if it is still fresh, with the sorted
+    /// positions of the items dropped for an empty word or data out of the dict file.
+    ///
+    /// The items are checked and the cache is written on the first load, or once the ifo,
+    /// idx or dict file changes.
+    fn new(files: &Files, ifo: &Ifo, dict: &Dict) -> Result<(Idx, Vec<usize>)> {
+        let stamp = file_stamp(&[&files.ifo, &files.idx, &files.dict])?;
+        let cache = cache_path(&files.ifo, "idx");
+        if let Some(cached) = cache.as_deref().and_then(|p| IdxCache::load(p, &stamp)) {
+            let dropped = cached.dropped();
+            return Ok((Idx::Cached(cached), dropped));
+        }
+
+        let mut items = Idx::read(files.idx.clone(), ifo)?;
+        let mut dropped = Vec::new();
+        let mut position = 0;
+        items.retain(|(word, offset, size)| {
+            let valid = !word.is_empty()
+                && offset
+                    .checked_add(*size)
+                    .is_some_and(|end| end <= dict.len());
+            if !valid {
+                dropped.push(position);
+            }
+            position += 1;
+            valid
+        });
+        if let Some(cache) = cache {
+            // The cache only makes the next start faster, so a failure to write is ignored.
+            let _ = IdxCache::write(&cache, &stamp, &items, &dropped);
+        }
+        Ok((Idx::Parsed(items), dropped))
+    }
+
+    /// Read the raw idx items and the size of the idx file, inflated if needed.
+    fn read_raw(path: &Path, ifo: &Ifo) -> Result<(Vec<RawIdxItem>, usize)> {
+        let f = File::open(path).with_context(|| format!("Failed to open idx file {:?}", path))?;
+        let f: Box<dyn BufRead> = if path.extension().is_some_and(|ext| ext == "gz") {
+            Box::new(BufReader::new(GzDecoder::new(f)))
+        } else {
+            Box::new(BufReader::new(f))
+        };
+
+        match ifo.offset_bits()? {
+            64 => Idx::read_bytes::<8, u64>(path, f),
+            _ => Idx::read_bytes::<4, u32>(path, f),
+        }
+    }
+
+    /// Parse the idx file.
+    ///
+    /// Invalid UTF-8 sequences are dropped from the headwords.
+    fn read(path: PathBuf, ifo: &Ifo) -> Result<Vec<IdxItem>> {
+        let (items, size) = Idx::read_raw(&path, ifo)?;
+
+        if ifo.idxfilesize != 0 && ifo.idxfilesize != size {
+            return Err(anyhow!(
+                "The size of idx file {:?} is {} bytes, but idxfilesize={} in the ifo file",
+                path,
+                size,
+                ifo.idxfilesize
+            ));
+        }
+
+        Ok(items
+            .into_iter()
+            .map(|(word, offset, size)| {
+                let word = String::from_utf8_lossy(&word)
+                    .chars()
+                    .filter(|&c| c != '\u{fffd}')
+                    .collect();
+                (word, offset, size)
+            })
+            .collect())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Idx::Parsed(items) => items.len(),
+            Idx::Cached(cache) => cache.len(),
+        }
+    }
+
+    /// Get the word, the offset and the size of the `i`-th item.
+    pub(crate) fn get(&self, i: usize) -> (&str, usize, usize) {
+        match self {
+            Idx::Parsed(items) => {
+                let (word, offset, size) = &items[i];
+                (word, *offset, *size)
             }
+            Idx::Cached(cache) => cache.get(i),
         }
-        Ok(items)
     }
 
-    fn new(path: PathBuf, version: Version) -> Result<Idx> {
-        match version {
-            Version::V242 => Ok(Idx {
-                items: Idx::read_bytes::<4, u32>(path)?,
-            }),
-            Version::V300 => Ok(Idx {
-                items: Idx::read_bytes::<8, u64>(path)?,
-            }),
-            Version::Unknown => Err(anyhow!("Wrong stardict version in idx file {:?}", path)),
+    fn word(&self, i: usize) -> &str {
+        self.get(i).0
+    }
+}
+
+/// The synonyms in the `.syn` file, with the position of the idx item each one refers to.
+#[derive(Debug)]
+struct Syn {
+    items: Vec<(String, usize)>,
+}
+
+impl Syn {
+    fn new(path: PathBuf) -> Result<Syn> {
+        let f = File::open(&path).with_context(|| format!("Failed to open syn file {:?}", path))?;
+        let mut f = BufReader::new(f);
+
+        let mut items = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            let read_bytes = f
+                .read_until(0, &mut buf)
+                .with_context(|| format!("Failed to parse syn file {:?}", path))?;
+            if read_bytes == 0 {
+                break;
+            }
+            if buf.last() == Some(&b'\0') {
+                buf.pop();
+            }
+
+            let mut b = [0; 4];
+            f.read_exact(&mut b)
+                .with_context(|| format!("Failed to parse syn file {:?}", path))?;
+            let index = u32::from_be_bytes(b) as usize;
+
+            items.push((String::from_utf8_lossy(&buf).into_owned(), index));
         }
+        Ok(Syn { items })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use flate2::{write::GzEncoder, Compression};
     use itertools::izip;
+    use std::fs::{create_dir_all, read_to_string, remove_file, write};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
 
-    use super::StarDict;
+    use super::{EntryPart, Idx, ItemProblems, Problem, StarDict, StarDictBuilder};
+    use crate::cache::test::tempdir;
+    use crate::collate::test::SORTED_HEADWORDS;
+    use crate::fuzzy::Ranking;
+    use crate::pattern::Pattern;
+    use std::collections::HashSet;
+
+    /// The file layout of a stardict written by [write_stardict].
+    #[derive(Default)]
+    struct Layout {
+        idx_gz: bool,
+        dict_dz: bool,
+        idx_offset_bits_64: bool,
+        /// Synonyms with the position of the word they refer to.
+        syn: Vec<(&'static str, u32)>,
+    }
+
+    /// Write the sorted `words` as a stardict named `name` into a directory of `root`.
+    fn write_stardict(root: &Path, name: &str, words: &[(&str, &str)], layout: Layout) -> PathBuf {
+        let dir = root.join(name);
+        create_dir_all(&dir).unwrap();
+
+        let mut idx = Vec::new();
+        let mut dict = Vec::new();
+        for (word, trans) in words {
+            idx.extend(word.as_bytes());
+            idx.push(0);
+            if layout.idx_offset_bits_64 {
+                idx.extend((dict.len() as u64).to_be_bytes());
+            } else {
+                idx.extend((dict.len() as u32).to_be_bytes());
+            }
+            idx.extend((trans.len() as u32).to_be_bytes());
+            dict.extend(trans.as_bytes());
+        }
+
+        let version = if layout.idx_offset_bits_64 {
+            "3.0.0\nidxoffsetbits=64"
+        } else {
+            "2.4.2"
+        };
+        let ifo = format!(
+            "StarDict's dict ifo file\nversion={}\nbookname={}\nwordcount={}\nidxfilesize={}\nsametypesequence=m\n",
+            version,
+            name,
+            words.len(),
+            idx.len()
+        );
+        let gzip = |data: &[u8]| {
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(data).unwrap();
+            e.finish().unwrap()
+        };
+
+        write(dir.join(format!("{}.ifo", name)), ifo).unwrap();
+        if layout.idx_gz {
+            write(dir.join(format!("{}.idx.gz", name)), gzip(&idx)).unwrap();
+        } else {
+            write(dir.join(format!("{}.idx", name)), idx).unwrap();
+        }
+        if layout.dict_dz {
+            write(dir.join(format!("{}.dict.dz", name)), gzip(&dict)).unwrap();
+        } else {
+            write(dir.join(format!("{}.dict", name)), dict).unwrap();
+        }
+        if !layout.syn.is_empty() {
+            let mut syn = Vec::new();
+            for (word, index) in layout.syn {
+                syn.extend(word.as_bytes());
+                syn.push(0);
+                syn.extend(index.to_be_bytes());
+            }
+            write(dir.join(format!("{}.syn", name)), syn).unwrap();
+        }
+        dir
+    }
 
     #[test]
     fn load_stardict() {
@@ -365,12 +1554,14 @@ mod test {
 
     #[test]
     fn lookup_offline() {
+        let _cache = tempdir();
         let stardict = StarDict::new("./stardict-heritage/cdict-gb".into()).unwrap();
-        stardict.exact_lookup("rust").unwrap();
+        stardict.exact_lookup("rust").unwrap().unwrap();
     }
 
     #[test]
     fn lookup_offline_fuzzy() {
+        let _cache = tempdir();
         let stardict = StarDict::new("./stardict-heritage/cdict-gb".into()).unwrap();
         let misspell = ["rst", "cago", "crade"];
         let correct = ["rust", "cargo", "crate"];
@@ -379,4 +1570,555 @@ mod test {
             fuzzy.iter().find(|w| w.word == cor).unwrap();
         }
     }
+
+    #[test]
+    fn load_file_variants() {
+        let root = tempdir();
+        let words = [("cargo", "货物"), ("crate", "板条箱"), ("rust", "铁锈")];
+        for (idx_gz, dict_dz) in [(false, false), (false, true), (true, false), (true, true)] {
+            let name = format!("variants-{}-{}", idx_gz, dict_dz);
+            let layout = Layout {
+                idx_gz,
+                dict_dz,
+                ..Default::default()
+            };
+            let stardict =
+                StarDict::new(write_stardict(root.path(), &name, &words, layout)).unwrap();
+            assert_eq!(
+                stardict.exact_lookup("rust").unwrap().unwrap().trans,
+                "铁锈"
+            );
+            assert_eq!(
+                stardict.exact_lookup("cargo").unwrap().unwrap().trans,
+                "货物"
+            );
+        }
+    }
+
+    #[test]
+    fn report_missing_files() {
+        let root = tempdir();
+        let dir = write_stardict(
+            root.path(),
+            "missing",
+            &[("rust", "铁锈")],
+            Layout::default(),
+        );
+        remove_file(dir.join("missing.dict")).unwrap();
+        let err = StarDict::new(dir.clone()).err().unwrap();
+        assert!(err.to_string().contains("Missing .dict or .dict.dz"));
+        remove_file(dir.join("missing.idx")).unwrap();
+        let err = StarDict::new(dir).err().unwrap();
+        assert!(err.to_string().contains("Missing .idx or .idx.gz"));
+    }
+
+    #[test]
+    fn check_broken_files() {
+        let root = tempdir();
+        let dir = write_stardict(
+            root.path(),
+            "broken",
+            &[("rust", "铁锈")],
+            Layout::default(),
+        );
+        assert!(StarDict::new(dir.clone()).unwrap().check().is_ok());
+        write(dir.join("broken.idx"), b"rust").unwrap();
+        let stardict = StarDict::new(dir).unwrap();
+        assert!(stardict.check().is_err());
+        assert!(stardict.exact_lookup("rust").is_err());
+    }
+
+    #[test]
+    fn verify_defects() {
+        let root = tempdir();
+        let words = [("cargo", "货物"), ("rust", "铁锈")];
+        let dir = write_stardict(root.path(), "verify", &words, Layout::default());
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        assert!(stardict.verify().problems.is_empty());
+
+        // Swap the headwords, make the second one invalid UTF-8 and point it past the end.
+        let mut idx = Vec::new();
+        for (word, offset, size) in [(&b"rust"[..], 0u32, 6u32), (b"c\xffargo", 6, 100)] {
+            idx.extend(word);
+            idx.push(0);
+            idx.extend(offset.to_be_bytes());
+            idx.extend(size.to_be_bytes());
+        }
+        write(dir.join("verify.idx"), idx).unwrap();
+        write(dir.join("verify.syn"), b"oxide\0\0\0\0\x05").unwrap();
+        let ifo = dir.join("verify.ifo");
+        write(&ifo, read_to_string(&ifo).unwrap() + "synwordcount=2\n").unwrap();
+
+        let report = StarDict::new(dir).unwrap().verify();
+        let kinds: Vec<_> = report.problems.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                "idxfilesize",
+                "invalid_utf8",
+                "unsorted",
+                "out_of_range",
+                "synwordcount",
+                "syn_target"
+            ]
+        );
+        assert_eq!(report.problems[3].items, [1]);
+    }
+
+    #[test]
+    fn cap_problem_items() {
+        let mut unsorted = ItemProblems::new("unsorted", |n, first| format!("{} {}", n, first));
+        for i in 0..150 {
+            unsorted.push(i, || "rust".to_owned());
+        }
+        let mut problems = Vec::new();
+        unsorted.report(&mut problems);
+        assert_eq!(problems[0].message, "150 rust");
+        assert_eq!(
+            problems[0].items,
+            (0..Problem::MAX_ITEMS).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn read_metadata() {
+        let root = tempdir();
+        let dir = write_stardict(
+            root.path(),
+            "metadata",
+            &[("rust", "铁锈")],
+            Layout::default(),
+        );
+        let ifo = dir.join("metadata.ifo");
+        let extra = "author=Alice\ndescription=line 1<br>line 2\ndicttype=wordnet\n";
+        write(&ifo, read_to_string(&ifo).unwrap() + extra).unwrap();
+
+        let stardict = StarDict::new(dir).unwrap();
+        assert_eq!(stardict.version(), "2.4.2");
+        assert_eq!(stardict.author(), "Alice");
+        assert_eq!(stardict.description(), "line 1<br>line 2");
+        assert_eq!(stardict.dicttype(), "wordnet");
+        assert_eq!(stardict.sametypesequence(), "m");
+        assert_eq!(stardict.email(), "");
+        assert_eq!(stardict.idx_offset_bits().unwrap(), 32);
+        assert_eq!(stardict.files().len(), 3);
+    }
+
+    #[test]
+    fn build_and_read_back() {
+        let mut builder = StarDictBuilder::new("built");
+        builder.description = "line 1\nline 2".into();
+        for (word, definition) in [
+            ("rust", "铁锈"),
+            ("cargo", "货物"),
+            ("Cargo", "Rust 的包管理器"),
+            ("cargo", "船货"),
+        ] {
+            builder.add(word, definition);
+        }
+        builder.add_synonym("oxide", "rust");
+        let root = tempdir();
+        let dir = root.path().join("built");
+        builder.write(&dir, "built").unwrap();
+
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        assert!(stardict.verify().problems.is_empty());
+        assert_eq!(stardict.description(), "line 1<br>line 2");
+        let trans: Vec<_> = stardict
+            .exact_lookup_all("cargo")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.trans)
+            .collect();
+        assert_eq!(trans, ["货物", "船货"]);
+        let entry = stardict.exact_lookup("oxide").unwrap().unwrap();
+        assert_eq!((entry.word, entry.trans.as_str()), ("rust", "铁锈"));
+
+        builder.add_synonym("iron", "fe");
+        assert!(builder.write(&dir, "built").is_err());
+    }
+
+    #[test]
+    fn read_entry_resources() {
+        let root = tempdir();
+        let dir = write_stardict(
+            root.path(),
+            "resources",
+            &[("rust", "snd:snd/rust.wav\nimg:rust.png")],
+            Layout::default(),
+        );
+        let ifo = dir.join("resources.ifo");
+        write(
+            &ifo,
+            read_to_string(&ifo)
+                .unwrap()
+                .replace("sametypesequence=m", "sametypesequence=r"),
+        )
+        .unwrap();
+        create_dir_all(dir.join("res/snd")).unwrap();
+        write(dir.join("res/snd/rust.wav"), b"RIFF").unwrap();
+
+        let stardict = StarDict::new(dir).unwrap();
+        let entry = stardict.exact_lookup("rust").unwrap().unwrap();
+        assert_eq!(
+            entry.resources(),
+            [("snd", "snd/rust.wav"), ("img", "rust.png")]
+        );
+        assert_eq!(stardict.sound(&entry).unwrap().unwrap(), b"RIFF");
+        assert_eq!(stardict.resource_keys().unwrap(), ["snd/rust.wav"]);
+        assert!(stardict.resource("rust.png").unwrap().is_none());
+    }
+
+    #[test]
+    fn honour_idx_offset_bits() {
+        let root = tempdir();
+        let words = [("cargo", "货物"), ("rust", "铁锈")];
+        let layout = Layout {
+            idx_offset_bits_64: true,
+            ..Default::default()
+        };
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "offset-bits-64",
+            &words,
+            layout,
+        ))
+        .unwrap();
+        assert_eq!(
+            stardict.exact_lookup("rust").unwrap().unwrap().trans,
+            "铁锈"
+        );
+
+        // 3.0.0 dictionaries without idxoffsetbits still use 32-bit offsets.
+        let dir = write_stardict(
+            root.path(),
+            "offset-bits-default",
+            &words,
+            Layout::default(),
+        );
+        let ifo = dir.join("offset-bits-default.ifo");
+        write(
+            &ifo,
+            read_to_string(&ifo).unwrap().replace("2.4.2", "3.0.0"),
+        )
+        .unwrap();
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        assert_eq!(
+            stardict.exact_lookup("rust").unwrap().unwrap().trans,
+            "铁锈"
+        );
+        assert!(stardict.warnings().is_empty());
+
+        write(
+            &ifo,
+            read_to_string(&ifo)
+                .unwrap()
+                .replace("idxfilesize=", "idxfilesize=1"),
+        )
+        .unwrap();
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        let err = stardict.exact_lookup("rust").err().unwrap();
+        assert!(err.to_string().contains("idxfilesize"));
+
+        // An unknown version is read as 3.0.0, with a warning for the caller.
+        write(&ifo, read_to_string(&ifo).unwrap().replace("3.0.0", "4.0")).unwrap();
+        let stardict = StarDict::new(dir).unwrap();
+        assert_eq!(stardict.version(), "unknown");
+        assert_eq!(stardict.warnings().len(), 1);
+    }
+
+    #[test]
+    fn lookup_synonyms() {
+        let root = tempdir();
+        let words = [("color", "颜色"), ("go", "去")];
+        let layout = Layout {
+            syn: vec![("colour", 0), ("went", 1)],
+            ..Default::default()
+        };
+        let stardict =
+            StarDict::new(write_stardict(root.path(), "synonyms", &words, layout)).unwrap();
+
+        let entry = stardict.exact_lookup("went").unwrap().unwrap();
+        assert_eq!((entry.word, entry.synonym), ("go", Some("went")));
+        assert_eq!(entry.trans, "去");
+        let entry = stardict.exact_lookup("go").unwrap().unwrap();
+        assert_eq!(entry.synonym, None);
+
+        let fuzzy = stardict.fuzzy_lookup("colur").unwrap();
+        let entry = fuzzy.iter().find(|e| e.synonym == Some("colour")).unwrap();
+        assert_eq!(entry.word, "color");
+    }
+
+    #[test]
+    fn drop_dangling_synonyms() {
+        let root = tempdir();
+        let words = [("color", "颜色"), ("go", "去")];
+        let layout = Layout {
+            syn: vec![("colour", 0), ("gone", 2)],
+            ..Default::default()
+        };
+        let stardict =
+            StarDict::new(write_stardict(root.path(), "dangling", &words, layout)).unwrap();
+
+        assert!(stardict.exact_lookup("gone").unwrap().is_none());
+        let fuzzy = stardict.fuzzy_lookup("gon").unwrap();
+        assert!(fuzzy.iter().all(|e| e.synonym != Some("gone")));
+        assert_eq!(
+            stardict.exact_lookup("colour").unwrap().unwrap().word,
+            "color"
+        );
+    }
+
+    #[test]
+    fn parse_typed_parts() {
+        let parts = EntryPart::parse(b"r\xc9\x92st\0rust, to corrode", "tm").unwrap();
+        assert_eq!(
+            parts,
+            [
+                EntryPart::Phonetic("rɒst".into()),
+                EntryPart::Meaning("rust, to corrode".into())
+            ]
+        );
+
+        let parts = EntryPart::parse(b"mrust\0W\0\0\0\x03RIFh<b>rust</b>\0", "").unwrap();
+        assert_eq!(
+            parts,
+            [
+                EntryPart::Meaning("rust".into()),
+                EntryPart::Wav(b"RIF".to_vec()),
+                EntryPart::Html("<b>rust</b>".into())
+            ]
+        );
+
+        assert!(EntryPart::parse(b"W\0\0\0\x09RIF", "").is_err());
+    }
+
+    #[test]
+    fn lookup_duplicated_headwords() {
+        let root = tempdir();
+        let words = [
+            ("lad", "男孩"),
+            ("lead", "领导"),
+            ("lead", "铅"),
+            ("leaf", "叶"),
+        ];
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "homographs",
+            &words,
+            Layout::default(),
+        ))
+        .unwrap();
+        let entries = stardict.exact_lookup_all("lead").unwrap();
+        let trans: Vec<_> = entries.iter().map(|e| e.trans.as_str()).collect();
+        assert_eq!(trans, ["领导", "铅"]);
+        assert!(stardict.exact_lookup_all("le").unwrap().is_empty());
+    }
+
+    #[test]
+    fn lookup_with_stardict_collation() {
+        let root = tempdir();
+        let words: Vec<_> = SORTED_HEADWORDS.iter().map(|w| (*w, *w)).collect();
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "collation",
+            &words,
+            Layout::default(),
+        ))
+        .unwrap();
+        for word in SORTED_HEADWORDS {
+            assert_eq!(stardict.exact_lookup(word).unwrap().unwrap().trans, word);
+        }
+    }
+
+    #[test]
+    fn lookup_fuzzy_ranked() {
+        let root = tempdir();
+        let words = [("tea", "茶"), ("the", "这"), ("toe", "脚趾")];
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "ranked",
+            &words,
+            Layout::default(),
+        ))
+        .unwrap();
+        let history = HashSet::new();
+        let entries = stardict
+            .fuzzy_lookup_ranked("teh", &Ranking::TYPO, &history)
+            .unwrap();
+        assert_eq!(entries[0].word, "the");
+        let history = HashSet::from(["tea".to_string()]);
+        let entries = stardict
+            .fuzzy_lookup_ranked("teh", &Ranking::HISTORY, &history)
+            .unwrap();
+        assert_eq!(entries[0].word, "tea");
+
+        // Words in the history are found behind more than enough nearer ones.
+        let mut words: Vec<String> = ["aaa", "aaaa"]
+            .iter()
+            .flat_map(|w| (0..=w.len()).map(move |i| w.split_at(i)))
+            .flat_map(|(a, b)| ('b'..='z').map(move |c| format!("{}{}{}", a, c, b)))
+            .collect();
+        words.push("aazz".to_owned());
+        words.sort();
+        words.dedup();
+        let words: Vec<_> = words.iter().map(|w| (w.as_str(), "")).collect();
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "ranked-many",
+            &words,
+            Layout::default(),
+        ))
+        .unwrap();
+        let history = HashSet::from(["aazz".to_string()]);
+        let entries = stardict
+            .fuzzy_lookup_ranked("aaaa", &Ranking::HISTORY, &history)
+            .unwrap();
+        assert_eq!(entries[0].word, "aazz");
+    }
+
+    #[test]
+    fn search_headword_patterns() {
+        let root = tempdir();
+        let words = [
+            ("col", "柱"),
+            ("Color", "颜色"),
+            ("colour", "颜色"),
+            ("colour", "着色"),
+            ("cool", "凉"),
+            ("dolour", "悲伤"),
+        ];
+        let stardict = StarDict::new(write_stardict(
+            root.path(),
+            "patterns",
+            &words,
+            Layout::default(),
+        ))
+        .unwrap();
+        assert_eq!(stardict.prefix_search("COLO").unwrap(), ["Color", "colour"]);
+        assert_eq!(
+            stardict.prefix_search("").unwrap(),
+            ["col", "Color", "colour", "cool", "dolour"]
+        );
+        assert!(stardict.prefix_search("colt").unwrap().is_empty());
+
+        let search = |pattern: &str| {
+            let pattern = Pattern::parse(pattern).unwrap().unwrap();
+            stardict
+                .pattern_search(&pattern)
+                .unwrap()
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(search("col*"), ["col", "Color", "colour"]);
+        assert_eq!(search("?olour"), ["colour", "dolour"]);
+        assert_eq!(search("c*l"), ["col", "cool"]);
+        assert_eq!(search(":ou?r$"), ["Color", "colour", "dolour"]);
+        assert_eq!(search(":^c.l$"), ["col"]);
+    }
+
+    #[test]
+    fn search_full_text() {
+        let root = tempdir();
+        let words = [
+            ("iron", "n. 铁; 熨斗"),
+            ("rust", "n. 铁锈; v. 生锈"),
+            ("rusty", "adj. 生锈的; 铁锈色的"),
+        ];
+        let dir = write_stardict(root.path(), "fulltext", &words, Layout::default());
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        let found = |entries: Vec<super::Entry>| -> Vec<String> {
+            entries.iter().map(|e| e.word.to_string()).collect()
+        };
+        assert_eq!(
+            found(stardict.fulltext_search("铁锈").unwrap()),
+            ["rust", "rusty"]
+        );
+        assert_eq!(found(stardict.fulltext_search("锈的").unwrap()), ["rusty"]);
+        assert_eq!(found(stardict.fulltext_search("ADJ").unwrap()), ["rusty"]);
+        assert!(stardict.fulltext_search("铜").unwrap().is_empty());
+
+        // The index is cached next to the dictionary and loaded by the next instance.
+        assert!(dir.join("fulltext.fts").is_file());
+        let stardict = StarDict::new(dir).unwrap();
+        assert_eq!(found(stardict.fulltext_search("熨斗").unwrap()), ["iron"]);
+    }
+
+    #[test]
+    fn load_cached_idx() {
+        let root = tempdir();
+        let words = [("apple", "苹果"), ("banana", "香蕉")];
+        let dir = write_stardict(root.path(), "cached", &words, Layout::default());
+        let stardict = StarDict::new(dir.clone()).unwrap();
+        assert!(matches!(stardict.data().unwrap().idx, Idx::Parsed(_)));
+        let stardict = StarDict::new(dir).unwrap();
+        assert!(matches!(stardict.data().unwrap().idx, Idx::Cached(_)));
+        assert_eq!(
+            stardict.exact_lookup("banana").unwrap().unwrap().trans,
+            "香蕉"
+        );
+
+        // A changed dictionary is parsed again.
+        let dir = write_stardict(
+            root.path(),
+            "cached",
+            &[("cherry", "樱桃")],
+            Layout::default(),
+        );
+        let stardict = StarDict::new(dir).unwrap();
+        assert!(matches!(stardict.data().unwrap().idx, Idx::Parsed(_)));
+        assert_eq!(
+            stardict.exact_lookup("cherry").unwrap().unwrap().trans,
+            "樱桃"
+        );
+
+        // The items out of the dict file are dropped once, and the synonyms still refer
+        // to the right items when loaded from the cache.
+        let words = [("apple", "苹果"), ("banana", "香蕉"), ("cherry", "樱桃")];
+        let layout = Layout {
+            syn: vec![("cerise", 2)],
+            ..Default::default()
+        };
+        let dir = write_stardict(root.path(), "cached-broken", &words, layout);
+        let idx = dir.join("cached-broken.idx");
+        let mut data = std::fs::read(&idx).unwrap();
+        data[21..25].copy_from_slice(&u32::MAX.to_be_bytes());
+        write(&idx, data).unwrap();
+        for _ in 0..2 {
+            let stardict = StarDict::new(dir.clone()).unwrap();
+            assert_eq!(stardict.wordcount(), 3);
+            assert!(stardict.exact_lookup("banana").unwrap().is_none());
+            let entry = stardict.exact_lookup("cerise").unwrap().unwrap();
+            assert_eq!((entry.word, entry.trans.as_str()), ("cherry", "樱桃"));
+        }
+        let stardict = StarDict::new(dir).unwrap();
+        assert!(matches!(stardict.data().unwrap().idx, Idx::Cached(_)));
+
+        // An offset so large that the end of the data overflows is dropped too.
+        let layout = Layout {
+            idx_offset_bits_64: true,
+            ..Default::default()
+        };
+        let dir = write_stardict(root.path(), "cached-overflow", &words, layout);
+        let idx = dir.join("cached-overflow.idx");
+        let mut data = std::fs::read(&idx).unwrap();
+        data[25..33].copy_from_slice(&u64::MAX.to_be_bytes());
+        write(&idx, data).unwrap();
+        let stardict = StarDict::new(dir).unwrap();
+        assert!(stardict.exact_lookup("banana").unwrap().is_none());
+        assert!(stardict.exact_lookup("cherry").unwrap().is_some());
+    }
+
+    #[test]
+    fn open_lazily() {
+        let root = tempdir();
+        let dir = write_stardict(root.path(), "lazy", &[("rust", "铁锈")], Layout::default());
+        write(dir.join("lazy.idx"), b"rust\0\0").unwrap();
+        let stardict = StarDict::new(dir).unwrap();
+        assert_eq!(stardict.dict_name(), "lazy");
+        assert_eq!(stardict.wordcount(), 1);
+        assert!(stardict.data.get().is_none());
+        assert!(stardict.exact_lookup("rust").is_err());
+    }
 }
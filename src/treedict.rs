@@ -0,0 +1,310 @@
+//! Tree dictionaries, whose headwords form a hierarchy such as the topics of a thesaurus.
+//!
+//! Instead of an idx file, a tree dictionary has a `.tdx` or `.tdx.gz` file listing its
+//! nodes in pre-order: each node is a headword, the offset and the size of its data in the
+//! dict file, and the number of its children, which follow it.
+use crate::stardict::{Dict, EntryPart, Ifo};
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use std::fs::read;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A node of a tree dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub word: String,
+    offset: usize,
+    size: usize,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Walk the subtree of the node in pre-order, with the depth of each node, this one
+    /// being at depth 0.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk {
+            stack: vec![(0, self)],
+        }
+    }
+}
+
+/// A pre-order walk of nodes, see [Node::walk] and [TreeDict::walk].
+pub struct Walk<'a> {
+    stack: Vec<(usize, &'a Node)>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (usize, &'a Node);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, node) = self.stack.pop()?;
+        self.stack
+            .extend(node.children.iter().rev().map(|child| (depth + 1, child)));
+        Some((depth, node))
+    }
+}
+
+/// A tree dictionary.
+pub struct TreeDict {
+    ifo: Ifo,
+    roots: Vec<Node>,
+    dict: Dict,
+}
+
+/// Get the `.tdx` or `.tdx.gz` file in a directory, preferring the uncompressed one.
+fn tdx_file(dir: &Path) -> Option<PathBuf> {
+    let mut files: Vec<_> = dir
+        .read_dir()
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.ends_with(".tdx") || name.ends_with(".tdx.gz")
+        })
+        .collect();
+    files.sort_by_key(|path| path.extension().is_some_and(|ext| ext == "gz"));
+    files.into_iter().next()
+}
+
+/// Read the ifo file of a tree dictionary, without reading its tdx and dict files.
+pub(crate) fn read_ifo(dir: &Path) -> Result<Ifo> {
+    let ifo = dir
+        .read_dir()
+        .with_context(|| format!("Failed to open directory {:?}", dir))?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|file| file.to_string_lossy().ends_with(".ifo"))
+        .with_context(|| format!("Missing .ifo file in {:?}", dir))?;
+    Ifo::new(ifo)
+}
+
+/// Is the directory a tree dictionary?
+pub fn is_tree_dict(dir: &Path) -> bool {
+    tdx_file(dir).is_some()
+}
+
+/// Parse the nodes of a tdx file, which may have several roots.
+fn parse_tdx(mut data: &[u8]) -> Result<Vec<Node>> {
+    let broken = || anyhow!("Broken tdx file");
+    let mut roots = Vec::new();
+    // The nodes whose children are being read, with the number of children left.
+    let mut stack: Vec<(Node, u32)> = Vec::new();
+    while !data.is_empty() {
+        let end = data.iter().position(|&b| b == 0).ok_or_else(broken)?;
+        let word = String::from_utf8_lossy(&data[..end]).into_owned();
+        let numbers = data.get(end + 1..end + 13).ok_or_else(broken)?;
+        let number = |i: usize| u32::from_be_bytes(numbers[i * 4..][..4].try_into().unwrap());
+        data = &data[end + 13..];
+
+        let node = Node {
+            word,
+            offset: number(0) as usize,
+            size: number(1) as usize,
+            children: Vec::new(),
+        };
+        stack.push((node, number(2)));
+        while let Some((_, 0)) = stack.last() {
+            let (node, _) = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some((parent, left)) => {
+                    parent.children.push(node);
+                    *left -= 1;
+                }
+                None => roots.push(node),
+            }
+        }
+    }
+    if !stack.is_empty() {
+        return Err(anyhow!(
+            "Truncated tdx file, {} nodes lack children",
+            stack.len()
+        ));
+    }
+    Ok(roots)
+}
+
+impl TreeDict {
+    /// Open the tree dictionary in a directory.
+    pub fn new(path: &Path) -> Result<TreeDict> {
+        let mut dict = None;
+        let mut dict_dz = None;
+        for entry in path
+            .read_dir()
+            .with_context(|| format!("Failed to open directory {:?}", path))?
+            .flatten()
+        {
+            let file = entry.path();
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            if name.ends_with(".dict") {
+                dict = Some(file);
+            } else if name.ends_with(".dict.dz") {
+                dict_dz = Some(file);
+            }
+        }
+        let tdx = tdx_file(path)
+            .with_context(|| format!("Missing .tdx or .tdx.gz file in {:?}", path))?;
+        let dict = dict
+            .or(dict_dz)
+            .with_context(|| format!("Missing .dict or .dict.dz file in {:?}", path))?;
+
+        let ifo = read_ifo(path)?;
+        let mut data = read(&tdx).with_context(|| format!("Failed to read tdx file {:?}", tdx))?;
+        if tdx.extension().is_some_and(|ext| ext == "gz") {
+            let mut inflated = Vec::new();
+            GzDecoder::new(data.as_slice())
+                .read_to_end(&mut inflated)
+                .with_context(|| format!("Failed to inflate tdx file {:?}", tdx))?;
+            data = inflated;
+        }
+        if ifo.tdxfilesize != 0 && ifo.tdxfilesize != data.len() {
+            return Err(anyhow!(
+                "The size of tdx file {:?} is {} bytes, but tdxfilesize={} in the ifo file",
+                tdx,
+                data.len(),
+                ifo.tdxfilesize
+            ));
+        }
+        let roots = parse_tdx(&data).with_context(|| format!("Failed to parse {:?}", tdx))?;
+
+        Ok(TreeDict {
+            ifo,
+            roots,
+            dict: Dict::new(dict)?,
+        })
+    }
+
+    /// Get the name of the tree dictionary.
+    pub fn dict_name(&self) -> &str {
+        &self.ifo.bookname
+    }
+
+    /// Get the number of the nodes.
+    pub fn wordcount(&self) -> usize {
+        self.ifo.wordcount
+    }
+
+    /// Get the top-level nodes.
+    pub fn roots(&self) -> &[Node] {
+        &self.roots
+    }
+
+    /// Walk all the nodes in pre-order, with the depth of each node, the roots being at 0.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk {
+            stack: self.roots.iter().rev().map(|root| (0, root)).collect(),
+        }
+    }
+
+    /// Find the first node with this headword in pre-order, ignoring the case of ASCII
+    /// letters, with its ancestors: the path from its root to the node.
+    pub fn find(&self, word: &str) -> Option<Vec<&Node>> {
+        let mut path = Vec::new();
+        for (depth, node) in self.walk() {
+            path.truncate(depth);
+            path.push(node);
+            if node.word.eq_ignore_ascii_case(word) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    /// Get the definition of a node, `None` for a node without data such as a category.
+    pub fn definition(&self, node: &Node) -> Result<Option<String>> {
+        if node.size == 0 {
+            return Ok(None);
+        }
+        if node.offset + node.size > self.dict.len() {
+            return Err(anyhow!(
+                "The data of {:?} is out of the dict file",
+                node.word
+            ));
+        }
+        let data = self.dict.get(node.offset, node.size)?;
+        let parts = EntryPart::parse(&data, &self.ifo.sametypesequence)
+            .with_context(|| format!("Failed to parse the data of {:?}", node.word))?;
+        Ok(Some(
+            parts
+                .iter()
+                .map(|part| part.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_tree_dict, read_ifo, TreeDict};
+    use flate2::{write::GzEncoder, Compression};
+    use std::fs::{remove_file, write};
+    use std::io::Write;
+
+    #[test]
+    fn walk_tree_dict() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        // (word, definition, number of children) in pre-order.
+        let nodes = [
+            ("animal", "", 2),
+            ("bird", "n. 鸟", 1),
+            ("sparrow", "n. 麻雀", 0),
+            ("fish", "n. 鱼", 0),
+            ("plant", "n. 植物", 0),
+        ];
+        let (mut tdx, mut dict) = (Vec::new(), Vec::new());
+        for (word, definition, children) in nodes {
+            tdx.extend(word.as_bytes());
+            tdx.push(0);
+            for n in [dict.len(), definition.len(), children] {
+                tdx.extend((n as u32).to_be_bytes());
+            }
+            dict.extend(definition.as_bytes());
+        }
+        let ifo = format!(
+            "StarDict's treedict ifo file\nversion=2.4.2\nbookname=tree\nwordcount=5\ntdxfilesize={}\nsametypesequence=m\n",
+            tdx.len()
+        );
+        write(dir.join("tree.ifo"), ifo).unwrap();
+        write(dir.join("tree.tdx"), &tdx).unwrap();
+        write(dir.join("tree.dict"), dict).unwrap();
+
+        assert!(is_tree_dict(dir));
+        let tree = TreeDict::new(dir).unwrap();
+        let walked: Vec<_> = tree
+            .walk()
+            .map(|(depth, n)| (depth, n.word.as_str()))
+            .collect();
+        assert_eq!(
+            walked,
+            [
+                (0, "animal"),
+                (1, "bird"),
+                (2, "sparrow"),
+                (1, "fish"),
+                (0, "plant")
+            ]
+        );
+
+        let path = tree.find("Sparrow").unwrap();
+        let words: Vec<_> = path.iter().map(|n| n.word.as_str()).collect();
+        assert_eq!(words, ["animal", "bird", "sparrow"]);
+        assert_eq!(tree.definition(path[2]).unwrap().unwrap(), "n. 麻雀");
+        assert!(tree.definition(path[0]).unwrap().is_none());
+        assert!(tree.find("tree").is_none());
+
+        assert_eq!(read_ifo(dir).unwrap().bookname, "tree");
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tdx).unwrap();
+        remove_file(dir.join("tree.tdx")).unwrap();
+        write(dir.join("tree.tdx.gz"), gz.finish().unwrap()).unwrap();
+        assert_eq!(TreeDict::new(dir).unwrap().walk().count(), 5);
+
+        write(dir.join("tree.tdx"), &tdx[..tdx.len() - 20]).unwrap();
+        assert!(TreeDict::new(dir).is_err());
+    }
+}